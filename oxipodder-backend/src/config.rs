@@ -0,0 +1,85 @@
+use std::{fs, path::{Path, PathBuf}, time::Duration};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::CONFIG_FILE_NAME;
+
+
+/// Settings read from `podder_config.json` in the library directory. Every field is optional
+/// in the file; anything left out keeps its default.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Config {
+    pub http: HttpConfig,
+    /// How many previous versions of the database to keep in `backups/`; 0 turns backups off.
+    pub backups: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            http: HttpConfig::default(),
+            backups: 5,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub user_agent: String,
+    /// `http://`, `https://` or `socks5://` proxy used for every request.
+    pub proxy: Option<String>,
+    pub connect_timeout_secs: u64,
+    /// How long a single read may stall before the request is abandoned.
+    pub read_timeout_secs: u64,
+    /// PEM files with extra certificate authorities to trust.
+    pub root_certificates: Vec<PathBuf>,
+    pub max_redirects: usize,
+    /// Extra attempts for a download after a network error or a 408, 429 or 5xx answer.
+    pub download_retries: u32,
+    /// Delay before the first retry; it doubles with every attempt, up to `retry_max_delay_secs`.
+    pub retry_base_delay_ms: u64,
+    pub retry_max_delay_secs: u64,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            user_agent: format!("oxipodder/{}", env!("CARGO_PKG_VERSION")),
+            proxy: None,
+            connect_timeout_secs: 10,
+            read_timeout_secs: 60,
+            root_certificates: Vec::new(),
+            max_redirects: 10,
+            download_retries: 3,
+            retry_base_delay_ms: 1000,
+            retry_max_delay_secs: 60,
+        }
+    }
+}
+
+impl HttpConfig {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout_secs)
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout_secs)
+    }
+}
+
+impl Config {
+    pub fn load(base_path: &Path) -> Result<Config> {
+        let config_path = base_path.join(CONFIG_FILE_NAME);
+        if !config_path.exists() {
+            return Ok(Config::default());
+        }
+
+        let content = fs::read_to_string(&config_path)
+            .with_context(|| format!("Failed to read config file: {:?}", config_path))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse config file: {:?}", config_path))
+    }
+}
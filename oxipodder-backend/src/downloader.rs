@@ -1,13 +1,16 @@
-use std::{any::Any, fs::{remove_file, File}, io::{Read, Write}, path::{Path, PathBuf}, process::Stdio, str::FromStr, sync::{mpsc, Arc}, thread::{self, JoinHandle}};
+use std::{collections::hash_map::RandomState, fmt, fs::{self, remove_file, File, OpenOptions}, hash::BuildHasher, io::{Read, Write}, path::{Path, PathBuf}, process::Stdio, sync::Arc, thread::{self, JoinHandle}, time::Duration};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use chrono::{DateTime, Utc};
-use crossbeam::{channel::{unbounded, Receiver}, queue::ArrayQueue};
-use filetime::{set_file_atime, set_file_times, FileTime};
-use reqwest::blocking::Response;
+use crossbeam::{channel::{unbounded, Receiver, Sender}, queue::ArrayQueue};
+use filetime::{set_file_times, FileTime};
+use reqwest::{blocking::{Client, Response}, header::{HeaderMap, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_RANGE, LAST_MODIFIED, LOCATION, RANGE, RETRY_AFTER}, redirect::Policy, StatusCode};
+use serde::{Deserialize, Serialize};
 use url::Url;
 
-use crate::helpers::create_reqwest_client;
+use crate::config::HttpConfig;
+use crate::helpers::{http_client_builder, sync_parent_dir};
+use crate::types::FeedAuth;
 
 
 pub struct DownloadProgress {
@@ -23,95 +26,519 @@ impl DownloadProgress {
 }
 
 
+pub struct DownloadRetry {
+    pub id: u32,
+    /// 1 for the first retry.
+    pub attempt: u32,
+    pub max_attempts: u32,
+    pub delay: Duration,
+    pub reason: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadErrorKind {
+    /// Connecting failed or the connection broke off.
+    Network,
+    HttpStatus(u16),
+    /// Writing the file to disk failed.
+    Io,
+    Transcode,
+    /// The server answered, but not with the file we asked for.
+    Verification,
+}
+
+impl fmt::Display for DownloadErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadErrorKind::Network => write!(f, "network error"),
+            DownloadErrorKind::HttpStatus(status) => write!(f, "HTTP {status}"),
+            DownloadErrorKind::Io => write!(f, "I/O error"),
+            DownloadErrorKind::Transcode => write!(f, "transcode error"),
+            DownloadErrorKind::Verification => write!(f, "verification failed"),
+        }
+    }
+}
+
+pub struct DownloadFailure {
+    pub id: u32,
+    pub kind: DownloadErrorKind,
+    pub message: String,
+}
+
 pub enum DownloadMessage {
     Started(DownloadProgress),
     Incremental(DownloadProgress),
     Completed(DownloadProgress),
-    Failed(String),
+    Retrying(DownloadRetry),
+    Failed(DownloadFailure),
     ThreadTerminated
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DownloadKind {
+    Audio,
+    /// Transcripts, chapters and other files stored next to the audio as-is.
+    Sidecar,
+}
+
 pub struct DownloadQueueElement {
     pub name: String,
     pub id: u32,
     pub url: Url,
     pub location: PathBuf,
-    pub pub_date: DateTime<Utc>
+    pub pub_date: DateTime<Utc>,
+    pub kind: DownloadKind,
+    /// Credentials of the feed; only sent while the request stays on `url`'s host, so set
+    /// this only when that is the feed's own host (see `Podcast::auth_for`).
+    pub auth: Option<FeedAuth>,
+    /// Size the feed announced for the file, if any.
+    pub expected_size: Option<u64>,
 }
 
-pub fn create_downloader(download_list: Vec<DownloadQueueElement>, threads: i32) -> Result<(Receiver<DownloadMessage>, Vec<JoinHandle<()>>)> {
-    let download_queue: Arc<ArrayQueue<DownloadQueueElement>> = Arc::new(ArrayQueue::new(download_list.len()));
+pub fn create_downloader(download_list: Vec<DownloadQueueElement>, threads: i32, http: &HttpConfig) -> Result<(Receiver<DownloadMessage>, Vec<JoinHandle<()>>)> {
+    let download_queue: Arc<ArrayQueue<DownloadQueueElement>> = Arc::new(ArrayQueue::new(download_list.len().max(1)));
     for e in download_list.into_iter() {
-        download_queue.push(e).map_err(|e| anyhow!("Failed to create download queue"))?;
+        download_queue.push(e).map_err(|_| anyhow!("Failed to create download queue"))?;
     }
 
+    // Redirects are followed in `send`, so that credentials stay on the feed's host.
+    let client = http_client_builder(http)?
+        .redirect(Policy::none())
+        .build()?;
+    let retry = RetryPolicy::from_config(http);
+    let max_redirects = http.max_redirects;
     let (tx, rx) = unbounded::<DownloadMessage>();
     let mut handles: Vec<JoinHandle<()>> = Vec::new();
 
     for _ in 0..threads {
         let download_queue = download_queue.clone();
+        let client = client.clone();
         let tx = tx.clone();
         let handle = thread::spawn(move || {
-            let client = create_reqwest_client().unwrap();
             while let Some(e) = download_queue.pop() {
-                let mut response: Response = match client.get(e.url.clone()).send() {
-                    Ok(res) => res,
-                    Err(e) => {
-                        tx.send(DownloadMessage::Failed(e.to_string())).unwrap(); //TODO: handle
-                        continue;
-                    },
+                let message = match download_with_retries(&client, &e, &tx, &retry, max_redirects) {
+                    Ok(progress) => DownloadMessage::Completed(progress),
+                    Err(err) => DownloadMessage::Failed(DownloadFailure {
+                        id: e.id,
+                        kind: err.kind,
+                        message: format!("{:#}", err.error),
+                    }),
                 };
+                if tx.send(message).is_err() {
+                    break;
+                }
+            }
+            let _ = tx.send(DownloadMessage::ThreadTerminated); // just to get the feel of it
+        });
+        handles.push(handle);
+    }
 
-                let is_mp3 = e.url.path().ends_with("mp3");
+    Ok((rx, handles))
+}
 
-                let total_size = response.content_length().unwrap_or_default();
-                let mut completed: u64 = 0;
-                let dl_path = if is_mp3 {e.location.clone()} else {PathBuf::from_str(&format!("/tmp/oxi_{}", e.id)).unwrap()};
-                let mut file = File::create(&dl_path).unwrap();
-                let mut buf = [0; 8192];
+#[derive(Clone, Copy)]
+struct RetryPolicy {
+    retries: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+}
 
-                tx.send(DownloadMessage::Started(DownloadProgress::new(e.id, total_size, completed))).unwrap();
-                let i = 0;
-                while let Ok(bytes_read) = response.read(&mut buf) {
-                    if bytes_read == 0 {break;}
+impl RetryPolicy {
+    fn from_config(http: &HttpConfig) -> Self {
+        Self {
+            retries: http.download_retries,
+            base_delay: Duration::from_millis(http.retry_base_delay_ms),
+            max_delay: Duration::from_secs(http.retry_max_delay_secs),
+        }
+    }
 
-                    file.write_all(&buf[..bytes_read]).unwrap();
-                    completed += bytes_read as u64;
-                    if i % 10 == 0 {
-                        tx.send(DownloadMessage::Incremental(DownloadProgress::new(e.id, total_size, completed))).unwrap();
-                    }
-                }
+    /// Exponential backoff with "equal jitter": somewhere between half and all of the
+    /// doubled delay, so that workers failing together do not retry in lockstep.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self.base_delay.saturating_mul(1 << (attempt - 1).min(16)).min(self.max_delay);
+        let jitter = RandomState::new().hash_one(attempt) % 1000;
+        delay / 2 + delay / 2 * jitter as u32 / 1000
+    }
+}
 
-                if !is_mp3 {
-                    let status = std::process::Command::new("ffmpeg")
-                        .args(&[
-                            "-y",
-                            "-i", dl_path.to_str().unwrap_or_default(),
-                            e.location.to_str().unwrap_or_default(),
-                        ])
-                        .stdout(Stdio::null())
-                        .stderr(Stdio::null())
-                        .status();
-
-                    match status {
-                        Ok(s) if s.success() => {},
-                        _ => {
-                            tx.send(DownloadMessage::Failed("Failed to Transcode".to_string())).unwrap();
-                        }
-                    }
-                    let _ = remove_file(dl_path);
-                }
+/// Why one attempt at a download failed, and whether trying again could help.
+struct AttemptError {
+    kind: DownloadErrorKind,
+    error: anyhow::Error,
+    transient: bool,
+    retry_after: Option<Duration>,
+}
+
+impl AttemptError {
+    fn new(kind: DownloadErrorKind, error: impl Into<anyhow::Error>) -> Self {
+        Self { kind, error: error.into(), transient: false, retry_after: None }
+    }
+
+    fn transient(self) -> Self {
+        Self { transient: true, ..self }
+    }
+}
+
+/// Anything not classified explicitly comes from working with the files on disk.
+impl<E: Into<anyhow::Error>> From<E> for AttemptError {
+    fn from(error: E) -> Self {
+        AttemptError::new(DownloadErrorKind::Io, error)
+    }
+}
+
+fn download_with_retries(client: &Client, e: &DownloadQueueElement, tx: &Sender<DownloadMessage>, retry: &RetryPolicy, max_redirects: usize) -> Result<DownloadProgress, AttemptError> {
+    let mut attempt = 0;
+    loop {
+        let err = match download(client, e, tx, max_redirects) {
+            Ok(progress) => return Ok(progress),
+            Err(err) if !err.transient || attempt >= retry.retries => return Err(err),
+            Err(err) => err,
+        };
+        attempt += 1;
+
+        let delay = match err.retry_after {
+            Some(delay) if delay > retry.max_delay => {
+                let error = err.error.context(format!("Server asked to retry after {}s", delay.as_secs()));
+                return Err(AttemptError { error, ..err });
+            },
+            Some(delay) => delay,
+            None => retry.backoff(attempt),
+        };
+        let _ = tx.send(DownloadMessage::Retrying(DownloadRetry {
+            id: e.id,
+            attempt,
+            max_attempts: retry.retries,
+            delay,
+            reason: format!("{}: {:#}", err.kind, err.error),
+        }));
+        thread::sleep(delay);
+    }
+}
+
+/// `Retry-After` as either a number of seconds or an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&Utc) - Utc::now()).to_std().unwrap_or_default())
+}
+
+fn is_text_content_type(response: &Response) -> bool {
+    let Some(content_type) = response.headers().get(CONTENT_TYPE).and_then(|v| v.to_str().ok()) else { return false };
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    mime.starts_with("text/") || mime == "application/xhtml+xml" || mime == "application/json"
+}
+
+/// Error pages served with an audio content type still start like a document.
+fn looks_like_markup(start: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(&start[..start.len().min(64)]).trim_start_matches('\u{feff}').trim_start().to_ascii_lowercase();
+    ["<!doctype", "<html", "<?xml", "<head", "<body"].iter().any(|tag| text.starts_with(tag))
+}
+
+/// Drops a partial download that should not be resumed.
+fn discard(part_path: &Path, meta_path: &Path) {
+    let _ = remove_file(part_path);
+    let _ = remove_file(meta_path);
+}
+
+fn is_transient(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 408 | 429 | 500 | 502 | 503 | 504)
+}
+
+/// Stored as `<file>.part.json` next to an unfinished `<file>.part`, so that a later run can
+/// pick the transfer up where it stopped.
+#[derive(Serialize, Deserialize)]
+struct PartialDownload {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl PartialDownload {
+    fn from_response(url: &Url, response: &Response) -> Self {
+        let header_value = |name| response.headers().get(name).and_then(|v| v.to_str().ok()).map(String::from);
+        Self { url: url.to_string(), etag: header_value(ETAG), last_modified: header_value(LAST_MODIFIED) }
+    }
 
-                let unix = FileTime::from_unix_time(e.pub_date.timestamp(), 0);
-                set_file_times(e.location, unix, unix).unwrap();
+    /// The validator to send in `If-Range`; weak ETags are not allowed there.
+    fn validator(&self) -> Option<&str> {
+        self.etag.as_deref().filter(|e| !e.starts_with("W/"))
+            .or(self.last_modified.as_deref())
+    }
+
+    fn same_version(&self, other: &PartialDownload) -> bool {
+        match (&self.etag, &other.etag) {
+            (Some(a), Some(b)) => a == b,
+            _ => self.last_modified.is_some() && self.last_modified == other.last_modified,
+        }
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Length of a `.part` file that can be continued, together with what it was downloaded from.
+fn resumable(part_path: &Path, meta_path: &Path, url: &Url) -> Option<(u64, PartialDownload)> {
+    let length = fs::metadata(part_path).ok()?.len();
+    let meta: PartialDownload = serde_json::from_slice(&fs::read(meta_path).ok()?).ok()?;
+    (length > 0 && meta.url == url.as_str() && meta.validator().is_some()).then_some((length, meta))
+}
 
-                tx.send(DownloadMessage::Completed(DownloadProgress::new(e.id, total_size, completed))).unwrap();
+fn content_range_start(headers: &HeaderMap) -> Option<u64> {
+    headers.get(CONTENT_RANGE)?.to_str().ok()?
+        .strip_prefix("bytes ")?
+        .split_once('-')?.0
+        .parse().ok()
+}
 
+fn send(client: &Client, e: &DownloadQueueElement, resume: Option<&(u64, PartialDownload)>, max_redirects: usize) -> Result<Response, AttemptError> {
+    let mut url = e.url.clone();
+    for _ in 0..=max_redirects {
+        let mut request = client.get(url.clone());
+        // Enclosures often redirect through CDNs and trackers, which must not see the feed's credentials.
+        if let Some(auth) = e.auth.as_ref().filter(|_| url.host_str() == e.url.host_str()) {
+            request = auth.apply(request);
+        }
+        if let Some((offset, meta)) = resume {
+            request = request.header(RANGE, format!("bytes={offset}-"));
+            if let Some(validator) = meta.validator() {
+                request = request.header(IF_RANGE, validator);
             }
-            tx.send(DownloadMessage::ThreadTerminated).unwrap(); // just to get the feel of it
-        });
-        handles.push(handle);
+        }
+        let response = request.send().map_err(|err| {
+            // A request that cannot even be built, e.g. because of a malformed header, fails the same way every time.
+            let builder = err.is_builder();
+            let err = AttemptError::new(DownloadErrorKind::Network, err);
+            if builder { err } else { err.transient() }
+        })?;
+
+        if !response.status().is_redirection() {
+            return Ok(response);
+        }
+        let status = response.status();
+        url = response.headers().get(LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|l| url.join(l).ok())
+            .ok_or_else(|| AttemptError::new(DownloadErrorKind::HttpStatus(status.as_u16()), anyhow!("{status} without a valid Location from {url}")))?;
     }
+    Err(AttemptError::new(DownloadErrorKind::Network, anyhow!("Too many redirects from {}", e.url)))
+}
 
-    Ok((rx, handles))
+fn download(client: &Client, e: &DownloadQueueElement, tx: &Sender<DownloadMessage>, max_redirects: usize) -> Result<DownloadProgress, AttemptError> {
+    let part_path = with_suffix(&e.location, ".part");
+    let meta_path = with_suffix(&e.location, ".part.json");
+
+    // Resume only when the server answers with the rest of the same file; `If-Range` makes it
+    // send the whole body instead if the file changed since the partial download.
+    let resume = resumable(&part_path, &meta_path, &e.url);
+    let mut response = send(client, e, resume.as_ref(), max_redirects)?;
+    let mut offset = 0;
+    if let Some((length, previous)) = &resume {
+        let partial = response.status() == StatusCode::PARTIAL_CONTENT;
+        if partial && content_range_start(response.headers()) == Some(*length)
+            && PartialDownload::from_response(&e.url, &response).same_version(previous) {
+            offset = *length;
+        } else if partial || response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            response = send(client, e, None, max_redirects)?;
+        }
+    }
+    let status = response.status();
+    let status_error = || AttemptError::new(
+        DownloadErrorKind::HttpStatus(status.as_u16()),
+        anyhow!("{}", status.canonical_reason().unwrap_or("Unexpected status")),
+    );
+    if is_transient(status) {
+        return Err(AttemptError { retry_after: retry_after(&response), ..status_error().transient() });
+    }
+    if !status.is_success() {
+        return Err(status_error());
+    }
+    if e.kind == DownloadKind::Audio && is_text_content_type(&response) {
+        let content_type = response.headers()[CONTENT_TYPE].to_str().unwrap_or_default();
+        return Err(AttemptError::new(DownloadErrorKind::Verification, anyhow!("Server sent {content_type} instead of audio")));
+    }
+
+    fs::write(&meta_path, serde_json::to_vec(&PartialDownload::from_response(&e.url, &response))?)
+        .with_context(|| format!("Failed to write {:?}", meta_path))?;
+    let mut file = if offset > 0 {
+        OpenOptions::new().append(true).open(&part_path)
+    } else {
+        File::create(&part_path)
+    }.with_context(|| format!("Failed to open {:?}", part_path))?;
+
+    let total_size = offset + response.content_length().unwrap_or_default();
+    let mut completed: u64 = offset;
+    let mut buf = [0; 8192];
+
+    let _ = tx.send(DownloadMessage::Started(DownloadProgress::new(e.id, total_size, completed)));
+    let i = 0;
+    loop {
+        // The `.part` file keeps what arrived so far, so the next attempt resumes from there.
+        let bytes_read = response.read(&mut buf)
+            .map_err(|err| AttemptError::new(DownloadErrorKind::Network, err).transient())?;
+        if bytes_read == 0 {break;}
+
+        if e.kind == DownloadKind::Audio && completed == 0 && looks_like_markup(&buf[..bytes_read]) {
+            discard(&part_path, &meta_path);
+            return Err(AttemptError::new(DownloadErrorKind::Verification, anyhow!("Server sent an HTML or XML page instead of audio")));
+        }
+        file.write_all(&buf[..bytes_read])?;
+        completed += bytes_read as u64;
+        if i % 10 == 0 {
+            let _ = tx.send(DownloadMessage::Incremental(DownloadProgress::new(e.id, total_size, completed)));
+        }
+    }
+    file.sync_all()?;
+    drop(file);
+
+    if response.content_length().is_some() && completed < total_size {
+        return Err(AttemptError::new(DownloadErrorKind::Network, anyhow!("Connection closed after {completed} of {total_size} bytes")).transient());
+    }
+    // Feeds are often a little off, e.g. because of dynamic ad insertion, so only a file
+    // far smaller than announced counts as broken.
+    if let Some(expected) = e.expected_size.filter(|x| response.content_length().is_none() && completed < x / 2) {
+        discard(&part_path, &meta_path);
+        return Err(AttemptError::new(DownloadErrorKind::Verification, anyhow!("Got {completed} bytes, but the feed announced {expected}")));
+    }
+
+    // Nothing appears under the final name until it is complete, so an existing file
+    // always means a finished download.
+    let needs_transcode = e.kind == DownloadKind::Audio && !e.url.path().ends_with("mp3");
+    let finished_path = if needs_transcode {
+        let staging_path = with_suffix(&e.location, ".transcoding");
+        let status = std::process::Command::new("ffmpeg")
+            .args([
+                "-y",
+                "-i", part_path.to_str().unwrap_or_default(),
+                "-f", "mp3",
+                staging_path.to_str().unwrap_or_default(),
+            ])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+
+        discard(&part_path, &meta_path);
+        match status {
+            Ok(s) if s.success() => staging_path,
+            _ => {
+                let _ = remove_file(&staging_path);
+                return Err(AttemptError::new(DownloadErrorKind::Transcode, anyhow!("Failed to Transcode")));
+            },
+        }
+    } else {
+        part_path
+    };
+
+    let unix = FileTime::from_unix_time(e.pub_date.timestamp(), 0);
+    set_file_times(&finished_path, unix, unix)?;
+    fs::rename(&finished_path, &e.location)
+        .with_context(|| format!("Failed to move {:?} into place", finished_path))?;
+    sync_parent_dir(&e.location);
+    let _ = remove_file(&meta_path);
+
+    Ok(DownloadProgress::new(e.id, total_size, completed))
 }
+
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    fn partial(etag: Option<&str>, last_modified: Option<&str>) -> PartialDownload {
+        PartialDownload {
+            url: "https://cdn.example.com/a.mp3".to_string(),
+            etag: etag.map(String::from),
+            last_modified: last_modified.map(String::from),
+        }
+    }
+
+    #[test]
+    fn content_range_start_reads_the_first_byte() {
+        let start = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_RANGE, HeaderValue::from_static(value));
+            content_range_start(&headers)
+        };
+        assert_eq!(start("bytes 1000-1999/2000"), Some(1000));
+        assert_eq!(start("bytes 0-99/*"), Some(0));
+        assert_eq!(start("bytes */2000"), None);
+        assert_eq!(start("items 10-20/30"), None);
+        assert_eq!(content_range_start(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn partial_downloads_resume_only_with_a_strong_validator() {
+        let last_modified = "Tue, 04 Jun 2024 10:30:00 GMT";
+        assert_eq!(partial(Some("\"v1\""), None).validator(), Some("\"v1\""));
+        assert_eq!(partial(Some("W/\"v1\""), Some(last_modified)).validator(), Some(last_modified));
+        assert_eq!(partial(Some("W/\"v1\""), None).validator(), None);
+        assert_eq!(partial(None, None).validator(), None);
+    }
+
+    #[test]
+    fn partial_downloads_compare_versions() {
+        let last_modified = Some("Tue, 04 Jun 2024 10:30:00 GMT");
+        assert!(partial(Some("\"v1\""), None).same_version(&partial(Some("\"v1\""), None)));
+        assert!(!partial(Some("\"v1\""), last_modified).same_version(&partial(Some("\"v2\""), last_modified)));
+        assert!(partial(None, last_modified).same_version(&partial(Some("\"v1\""), last_modified)));
+        assert!(!partial(None, None).same_version(&partial(None, None)));
+    }
+
+    #[test]
+    fn backoff_doubles_within_equal_jitter() {
+        let policy = RetryPolicy { retries: 10, base_delay: Duration::from_secs(1), max_delay: Duration::from_secs(60) };
+        for (attempt, full) in [(1, 1), (2, 2), (3, 4), (6, 32), (7, 60), (40, 60)] {
+            let full = Duration::from_secs(full);
+            for _ in 0..20 {
+                let delay = policy.backoff(attempt);
+                assert!(delay >= full / 2 && delay <= full, "attempt {attempt}: {delay:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn backoff_follows_the_config() {
+        let http = HttpConfig { retry_base_delay_ms: 0, ..HttpConfig::default() };
+        assert_eq!(RetryPolicy::from_config(&http).backoff(1), Duration::ZERO);
+
+        let http = HttpConfig { retry_base_delay_ms: 200, retry_max_delay_secs: 0, ..HttpConfig::default() };
+        assert_eq!(RetryPolicy::from_config(&http).backoff(5), Duration::ZERO);
+    }
+
+    #[test]
+    fn markup_is_recognised() {
+        for body in [
+            &b"<!DOCTYPE html><html><body>Not found</body></html>"[..],
+            b"<html>",
+            b"  \r\n<HTML lang=\"en\">",
+            b"\xef\xbb\xbf<?xml version=\"1.0\"?><Error><Code>AccessDenied</Code></Error>",
+            b"<head><title>403</title></head>",
+            b"<body>",
+        ] {
+            assert!(looks_like_markup(body), "{:?}", String::from_utf8_lossy(body));
+        }
+    }
+
+    #[test]
+    fn audio_is_not_markup() {
+        for body in [
+            &b"ID3\x04\x00\x00\x00\x00\x00\x00"[..],
+            b"\xff\xfb\x90\x64\x00",
+            b"\x00\x00\x00\x20ftypM4A ",
+            b"OggS\x00\x02",
+            b"",
+            b"<",
+        ] {
+            assert!(!looks_like_markup(body), "{:?}", body);
+        }
+    }
+}
+
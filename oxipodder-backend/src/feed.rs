@@ -0,0 +1,441 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use std::collections::BTreeMap;
+
+use rss::{extension::{itunes::{ITunesChannelExtension, ITunesItemExtension}, Extension}, Channel};
+
+use crate::types::{Chapters, Enclosure, EpisodeType, Funding, ItunesEpisode, ItunesPodcast, Person, PodcastIndexEpisode, PodcastIndexPodcast, Transcript};
+
+const PODCAST_NAMESPACES: [&str; 2] = [
+    "https://podcastindex.org/namespace/1.0",
+    "https://github.com/Podcastindex-org/podcast-namespace/blob/main/docs/1.0.md",
+];
+
+
+pub struct ParsedFeed {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub link: Option<String>,
+    /// Where the publisher says the feed now lives (`<itunes:new-feed-url>`).
+    pub new_feed_url: Option<String>,
+    pub itunes: ItunesPodcast,
+    pub podcast_index: PodcastIndexPodcast,
+    pub items: Vec<FeedItem>,
+}
+
+pub struct FeedItem {
+    pub guid: Option<String>,
+    pub title: Option<String>,
+    pub enclosure: Option<Enclosure>,
+    pub pub_date: Option<DateTime<Utc>>,
+    pub itunes: ItunesEpisode,
+    pub podcast_index: PodcastIndexEpisode,
+}
+
+/// Parses an RSS or Atom document, picking the format from its root element.
+pub fn parse_feed(content: &[u8]) -> Result<ParsedFeed> {
+    match Channel::read_from(content) {
+        Ok(channel) => Ok(ParsedFeed::from(channel)),
+        Err(rss::Error::InvalidStartTag) => match atom_syndication::Feed::read_from(content) {
+            Ok(feed) => Ok(ParsedFeed::from(feed)),
+            Err(atom_syndication::Error::InvalidStartTag) => Err(anyhow!("the input is neither an RSS nor an Atom feed")),
+            Err(e) => Err(e.into()),
+        },
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl From<Channel> for ParsedFeed {
+    fn from(channel: Channel) -> Self {
+        let prefix = channel.namespaces.iter()
+            .find(|(_, uri)| PODCAST_NAMESPACES.contains(&uri.as_str()))
+            .map(|(prefix, _)| prefix.clone())
+            .unwrap_or_else(|| "podcast".to_string());
+        let empty = BTreeMap::new();
+        let podcast_index = podcast_index_podcast(channel.extensions.get(&prefix).unwrap_or(&empty));
+
+        let items = channel.items.into_iter().map(|item| FeedItem {
+            podcast_index: podcast_index_episode(item.extensions.get(&prefix).unwrap_or(&empty)),
+            guid: item.guid.map(|g| g.value),
+            title: item.title,
+            enclosure: item.enclosure.map(|e| Enclosure {
+                url: e.url,
+                length: e.length.parse().unwrap_or_default(),
+                mime_type: e.mime_type,
+            }),
+            pub_date: item.pub_date.as_deref().and_then(parse_date),
+            itunes: item.itunes_ext.map(ItunesEpisode::from).unwrap_or_default(),
+        }).collect();
+
+        ParsedFeed {
+            title: Some(channel.title),
+            description: Some(channel.description),
+            link: Some(channel.link),
+            new_feed_url: channel.itunes_ext.as_ref().and_then(|i| i.new_feed_url.clone()),
+            itunes: channel.itunes_ext.map(ItunesPodcast::from).unwrap_or_default(),
+            podcast_index,
+            items,
+        }
+    }
+}
+
+impl From<atom_syndication::Feed> for ParsedFeed {
+    fn from(feed: atom_syndication::Feed) -> Self {
+        let link = feed.links.iter()
+            .find(|l| l.rel == "alternate")
+            .map(|l| l.href.clone());
+
+        let items = feed.entries.into_iter().map(|entry| FeedItem {
+            guid: Some(entry.id).filter(|id| !id.is_empty()),
+            title: Some(entry.title.value),
+            enclosure: entry.links.into_iter().find(|l| l.rel == "enclosure").map(|l| Enclosure {
+                url: l.href,
+                length: l.length.and_then(|len| len.parse().ok()).unwrap_or_default(),
+                mime_type: l.mime_type.unwrap_or_default(),
+            }),
+            pub_date: Some(entry.published.unwrap_or(entry.updated).into()),
+            itunes: ItunesEpisode::default(),
+            podcast_index: PodcastIndexEpisode::default(),
+        }).collect();
+
+        ParsedFeed {
+            title: Some(feed.title.value),
+            description: feed.subtitle.map(|s| s.value),
+            link,
+            new_feed_url: None,
+            itunes: ItunesPodcast::default(),
+            podcast_index: PodcastIndexPodcast::default(),
+            items,
+        }
+    }
+}
+
+impl From<ITunesChannelExtension> for ItunesPodcast {
+    fn from(ext: ITunesChannelExtension) -> Self {
+        ItunesPodcast {
+            author: ext.author,
+            image: ext.image,
+            explicit: ext.explicit.as_deref().and_then(parse_flag),
+            summary: ext.summary,
+            categories: ext.categories.into_iter().map(|c| c.text).collect(),
+            podcast_type: ext.r#type,
+        }
+    }
+}
+
+impl From<ITunesItemExtension> for ItunesEpisode {
+    fn from(ext: ITunesItemExtension) -> Self {
+        ItunesEpisode {
+            episode: ext.episode.and_then(|e| e.trim().parse().ok()),
+            season: ext.season.and_then(|s| s.trim().parse().ok()),
+            episode_type: ext.episode_type.as_deref().and_then(parse_episode_type),
+            duration: ext.duration.as_deref().and_then(parse_duration),
+            explicit: ext.explicit.as_deref().and_then(parse_flag),
+            summary: ext.summary,
+            author: ext.author,
+            image: ext.image,
+        }
+    }
+}
+
+fn podcast_index_podcast(ext: &BTreeMap<String, Vec<Extension>>) -> PodcastIndexPodcast {
+    let locked = ext.get("locked").and_then(|l| l.first());
+    PodcastIndexPodcast {
+        guid: ext.get("guid").and_then(|g| g.first()).and_then(extension_text),
+        locked: locked.and_then(extension_text).and_then(|l| parse_flag(&l)),
+        locked_owner: locked.and_then(|l| l.attrs.get("owner").cloned()),
+        funding: ext.get("funding").into_iter().flatten().filter_map(|f| Some(Funding {
+            url: f.attrs.get("url")?.clone(),
+            message: extension_text(f),
+        })).collect(),
+        persons: persons(ext),
+    }
+}
+
+fn podcast_index_episode(ext: &BTreeMap<String, Vec<Extension>>) -> PodcastIndexEpisode {
+    PodcastIndexEpisode {
+        transcripts: ext.get("transcript").into_iter().flatten().filter_map(|t| Some(Transcript {
+            url: t.attrs.get("url")?.clone(),
+            mime_type: t.attrs.get("type").cloned().unwrap_or_default(),
+            language: t.attrs.get("language").cloned(),
+            rel: t.attrs.get("rel").cloned(),
+        })).collect(),
+        chapters: ext.get("chapters").and_then(|c| c.first()).and_then(|c| Some(Chapters {
+            url: c.attrs.get("url")?.clone(),
+            mime_type: c.attrs.get("type").cloned().unwrap_or_default(),
+        })),
+        persons: persons(ext),
+    }
+}
+
+fn persons(ext: &BTreeMap<String, Vec<Extension>>) -> Vec<Person> {
+    ext.get("person").into_iter().flatten().filter_map(|p| Some(Person {
+        name: extension_text(p)?,
+        role: p.attrs.get("role").cloned(),
+        group: p.attrs.get("group").cloned(),
+        img: p.attrs.get("img").cloned(),
+        href: p.attrs.get("href").cloned(),
+    })).collect()
+}
+
+fn extension_text(ext: &Extension) -> Option<String> {
+    ext.value.as_deref().map(str::trim).filter(|v| !v.is_empty()).map(String::from)
+}
+
+fn parse_flag(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "yes" | "true" | "explicit" => Some(true),
+        "no" | "false" | "clean" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_episode_type(value: &str) -> Option<EpisodeType> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "full" => Some(EpisodeType::Full),
+        "trailer" => Some(EpisodeType::Trailer),
+        "bonus" => Some(EpisodeType::Bonus),
+        _ => None,
+    }
+}
+
+/// Accepts plain seconds as well as `MM:SS` and `HH:MM:SS`.
+fn parse_duration(value: &str) -> Option<u64> {
+    value.trim().split(':').try_fold(0u64, |total, part| {
+        let part: f64 = part.trim().parse().ok()?;
+        Some(total * 60 + part as u64)
+    })
+}
+
+/// Parses the dates podcast feeds actually publish: RFC 822 with or without seconds,
+/// weekday or a numeric zone, named zones like `EST`, and ISO 8601.
+pub fn parse_date(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+    if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+        return Some(date.into());
+    }
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Some(date.into());
+    }
+    parse_iso8601(value).or_else(|| parse_rfc822_lenient(value))
+}
+
+fn parse_iso8601(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.strip_suffix('Z').map(|v| format!("{v}+00:00")).unwrap_or_else(|| value.to_string());
+    for format in ["%Y-%m-%dT%H:%M:%S%.f%:z", "%Y-%m-%dT%H:%M:%S%.f%z", "%Y-%m-%dT%H:%M%:z", "%Y-%m-%d %H:%M:%S%.f%:z"] {
+        if let Ok(date) = DateTime::parse_from_str(&value, format) {
+            return Some(date.into());
+        }
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"] {
+        if let Ok(date) = NaiveDateTime::parse_from_str(&value, format) {
+            return Some(date.and_utc());
+        }
+    }
+    NaiveDate::parse_from_str(&value, "%Y-%m-%d").ok()
+        .map(|d| d.and_time(NaiveTime::MIN).and_utc())
+}
+
+fn parse_rfc822_lenient(value: &str) -> Option<DateTime<Utc>> {
+    let cleaned = value.replace(',', " ");
+    let mut tokens: Vec<&str> = cleaned.split_whitespace().collect();
+    if tokens.first().is_some_and(|t| t.chars().all(char::is_alphabetic) && month_number(t).is_none()) {
+        tokens.remove(0);
+    }
+    if tokens.len() < 3 {
+        return None;
+    }
+    if month_number(tokens[0]).is_some() {
+        tokens.swap(0, 1);
+    }
+
+    let day: u32 = tokens[0].trim_end_matches('.').parse().ok()?;
+    let month = month_number(tokens[1])?;
+    let year: i32 = match tokens[2].parse().ok()? {
+        y @ 0..50 => y + 2000,
+        y @ 50..100 => y + 1900,
+        y => y,
+    };
+
+    let mut time = tokens.get(3).copied().unwrap_or("00:00").split(':');
+    let hour: u32 = time.next()?.parse().ok()?;
+    let minute: u32 = time.next().unwrap_or("0").parse().ok()?;
+    let second = time.next().unwrap_or("0");
+    let second: u32 = second.split('.').next()?.parse().ok()?;
+
+    let offset = match tokens.get(4) {
+        Some(zone) => zone_offset(zone)?,
+        None => 0,
+    };
+
+    let naive = NaiveDate::from_ymd_opt(year, month, day)?.and_hms_opt(hour, minute, second)?;
+    FixedOffset::east_opt(offset)?.from_local_datetime(&naive).single().map(|d| d.into())
+}
+
+fn month_number(name: &str) -> Option<u32> {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let months = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+    if name.len() < 3 {
+        return None;
+    }
+    months.iter().position(|m| name.starts_with(m)).map(|i| i as u32 + 1)
+}
+
+/// Offset in seconds east of UTC for a numeric (`-0500`, `+05:30`) or named zone.
+fn zone_offset(zone: &str) -> Option<i32> {
+    if let Some(sign) = zone.chars().next().filter(|c| *c == '+' || *c == '-') {
+        let digits: String = zone[1..].chars().filter(|c| *c != ':').collect();
+        if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let hours: i32 = digits[..2].parse().ok()?;
+        let minutes: i32 = digits[2..].parse().ok()?;
+        let offset = hours * 3600 + minutes * 60;
+        return Some(if sign == '-' { -offset } else { offset });
+    }
+
+    let hours = match zone.to_ascii_uppercase().as_str() {
+        "UT" | "UTC" | "GMT" | "Z" => 0,
+        "EST" => -5, "EDT" => -4,
+        "CST" => -6, "CDT" => -5,
+        "MST" => -7, "MDT" => -6,
+        "PST" => -8, "PDT" => -7,
+        "AKST" => -9, "AKDT" => -8,
+        "HST" => -10,
+        "BST" | "CET" => 1,
+        "CEST" | "EET" => 2,
+        "EEST" | "MSK" => 3,
+        "JST" => 9,
+        "AEST" => 10, "AEDT" => 11,
+        _ => return None,
+    };
+    Some(hours * 3600)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().into()
+    }
+
+    fn assert_date(value: &str, expected: &str) {
+        assert_eq!(parse_date(value), Some(utc(expected)), "{value:?}");
+    }
+
+    #[test]
+    fn rfc822_dates() {
+        assert_date("Tue, 04 Jun 2024 10:30:00 +0000", "2024-06-04T10:30:00Z");
+        assert_date("Tue, 4 Jun 2024 10:30:00 -0500", "2024-06-04T15:30:00Z");
+        assert_date("04 Jun 2024 10:30:00 GMT", "2024-06-04T10:30:00Z");
+    }
+
+    #[test]
+    fn rfc822_dates_without_seconds() {
+        assert_date("Tue, 04 Jun 2024 10:30 +0000", "2024-06-04T10:30:00Z");
+        assert_date("Tue, 04 Jun 2024 10:30 CEST", "2024-06-04T08:30:00Z");
+        assert_date("04 Jun 2024", "2024-06-04T00:00:00Z");
+    }
+
+    #[test]
+    fn named_time_zones() {
+        assert_date("Tue, 04 Jun 2024 10:30:00 EST", "2024-06-04T15:30:00Z");
+        assert_date("Tue, 04 Jun 2024 10:30:00 PDT", "2024-06-04T17:30:00Z");
+        assert_date("Tue, 04 Jun 2024 10:30:00 UTC", "2024-06-04T10:30:00Z");
+        assert_date("Tue, 04 Jun 2024 10:30:00 CET", "2024-06-04T09:30:00Z");
+        assert_date("Tue, 04 Jun 2024 10:30:00 JST", "2024-06-04T01:30:00Z");
+        assert_date("Tue, 04 Jun 2024 10:30:00 +05:30", "2024-06-04T05:00:00Z");
+        assert_eq!(parse_date("Tue, 04 Jun 2024 10:30:00 XYZ"), None);
+    }
+
+    #[test]
+    fn two_digit_years() {
+        assert_date("Tue, 04 Jun 24 10:30:00 +0000", "2024-06-04T10:30:00Z");
+        assert_date("Fri, 04 Jun 99 10:30:00 +0000", "1999-06-04T10:30:00Z");
+        assert_date("04 Jun 24 10:30 EDT", "2024-06-04T14:30:00Z");
+    }
+
+    #[test]
+    fn rfc822_variants() {
+        assert_date("Tuesday, 04 June 2024 10:30:00 +0200", "2024-06-04T08:30:00Z");
+        assert_date("Tue, Jun 04 2024 10:30:00 +0000", "2024-06-04T10:30:00Z");
+        assert_date("Tue 04 Jun. 2024 10:30:00.250 GMT", "2024-06-04T10:30:00Z");
+        assert_date("  Tue, 04 Jun 2024 10:30:00 +0000\n", "2024-06-04T10:30:00Z");
+    }
+
+    #[test]
+    fn iso8601_with_zone() {
+        assert_date("2024-06-04T10:30:00Z", "2024-06-04T10:30:00Z");
+        assert_date("2024-06-04T10:30:00.123Z", "2024-06-04T10:30:00.123Z");
+        assert_date("2024-06-04T10:30:00+02:00", "2024-06-04T08:30:00Z");
+        assert_date("2024-06-04T10:30:00-0500", "2024-06-04T15:30:00Z");
+        assert_date("2024-06-04T10:30Z", "2024-06-04T10:30:00Z");
+    }
+
+    #[test]
+    fn iso8601_without_zone() {
+        assert_date("2024-06-04T10:30:00", "2024-06-04T10:30:00Z");
+        assert_date("2024-06-04 10:30:00", "2024-06-04T10:30:00Z");
+        assert_date("2024-06-04T10:30", "2024-06-04T10:30:00Z");
+        assert_date("2024-06-04", "2024-06-04T00:00:00Z");
+    }
+
+    #[test]
+    fn unparsable_dates() {
+        for value in ["", "   ", "not a date", "yesterday", "Tue, 31 Feb 2024 10:30:00 +0000", "2024-13-01", "04 Foo 2024 10:30:00 GMT"] {
+            assert_eq!(parse_date(value), None, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("3600"), Some(3600));
+        assert_eq!(parse_duration(" 90 "), Some(90));
+        assert_eq!(parse_duration("12.5"), Some(12));
+        assert_eq!(parse_duration("45:30"), Some(2730));
+        assert_eq!(parse_duration("01:02:03"), Some(3723));
+        assert_eq!(parse_duration("1:02:03.5"), Some(3723));
+        assert_eq!(parse_duration("0:00"), Some(0));
+        for value in ["", "abc", "1:xx", "1::2"] {
+            assert_eq!(parse_duration(value), None, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn itunes_item_metadata() {
+        let feed = parse_feed(br#"<?xml version="1.0"?>
+            <rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd"><channel>
+                <title>Show</title><link>https://example.com</link><description>d</description>
+                <itunes:explicit>yes</itunes:explicit>
+                <item><guid>a</guid><title>Pilot</title>
+                    <itunes:episode> 3 </itunes:episode><itunes:season>2</itunes:season>
+                    <itunes:episodeType>Bonus</itunes:episodeType><itunes:duration>1:02:03</itunes:duration>
+                    <itunes:explicit>clean</itunes:explicit>
+                </item>
+            </channel></rss>"#).unwrap();
+
+        assert_eq!(feed.itunes.explicit, Some(true));
+        let itunes = &feed.items[0].itunes;
+        assert_eq!((itunes.episode, itunes.season), (Some(3), Some(2)));
+        assert!(itunes.episode_type == Some(EpisodeType::Bonus));
+        assert_eq!(itunes.duration, Some(3723));
+        assert_eq!(itunes.explicit, Some(false));
+    }
+
+    #[test]
+    fn feeds_keep_unparsable_dates_unset() {
+        let feed = parse_feed(br#"<?xml version="1.0"?>
+            <rss version="2.0"><channel><title>Dates</title><link>https://example.com</link><description>d</description>
+                <item><guid>a</guid><title>Good</title><pubDate>Tue, 04 Jun 2024 10:30 EST</pubDate></item>
+                <item><guid>b</guid><title>Bad</title><pubDate>sometime last week</pubDate></item>
+            </channel></rss>"#).unwrap();
+
+        assert_eq!(feed.items[0].pub_date, Some(utc("2024-06-04T15:30:00Z")));
+        assert_eq!(feed.items[1].pub_date, None);
+    }
+}
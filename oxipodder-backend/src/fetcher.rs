@@ -0,0 +1,235 @@
+use std::{fmt, str::FromStr, sync::Arc, thread::{self, JoinHandle}, time::Duration};
+
+use anyhow::{anyhow, Result};
+use crossbeam::{channel::{unbounded, Receiver}, queue::ArrayQueue};
+use reqwest::{blocking::Client, header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, LOCATION}, redirect::Policy, StatusCode};
+use url::Url;
+
+use crate::{config::HttpConfig, helpers::http_client_builder, types::FeedAuth};
+
+
+pub struct RefreshOptions {
+    pub threads: usize,
+    /// Limit for a whole feed request, on top of the HTTP client's own timeouts.
+    pub timeout: Duration,
+    pub http: HttpConfig,
+    pub redownload_on_enclosure_change: bool,
+    pub withdrawn_policy: WithdrawnPolicy,
+    /// How long an episode has to be missing from its feed before it counts as withdrawn.
+    pub withdrawn_grace: Duration,
+}
+
+impl Default for RefreshOptions {
+    fn default() -> Self {
+        Self {
+            threads: 8,
+            timeout: Duration::from_secs(30),
+            http: HttpConfig::default(),
+            redownload_on_enclosure_change: false,
+            withdrawn_policy: WithdrawnPolicy::Keep,
+            withdrawn_grace: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+}
+
+/// What to do with the downloaded file of an episode that dropped out of its feed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum WithdrawnPolicy {
+    Keep,
+    /// Move the file into a `.withdrawn` folder inside the podcast directory.
+    Hide,
+    Delete,
+}
+
+impl FromStr for WithdrawnPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "keep" => Ok(WithdrawnPolicy::Keep),
+            "hide" => Ok(WithdrawnPolicy::Hide),
+            "delete" => Ok(WithdrawnPolicy::Delete),
+            _ => Err(anyhow!("Unknown withdrawn episode policy: {s}")),
+        }
+    }
+}
+
+
+pub enum RefreshStatus {
+    Updated { new_episodes: usize, updated_episodes: usize, withdrawn_episodes: usize },
+    NotModified,
+    HttpError(String),
+    ParseError(String),
+    Timeout,
+}
+
+impl fmt::Display for RefreshStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RefreshStatus::Updated { new_episodes, updated_episodes, withdrawn_episodes } => {
+                write!(f, "{new_episodes} new, {updated_episodes} updated, {withdrawn_episodes} withdrawn episodes")
+            },
+            RefreshStatus::NotModified => write!(f, "not modified"),
+            RefreshStatus::HttpError(e) => write!(f, "HTTP error: {e}"),
+            RefreshStatus::ParseError(e) => write!(f, "parse error: {e}"),
+            RefreshStatus::Timeout => write!(f, "timed out"),
+        }
+    }
+}
+
+impl RefreshStatus {
+    pub fn is_failure(&self) -> bool {
+        matches!(self, RefreshStatus::HttpError(_) | RefreshStatus::ParseError(_) | RefreshStatus::Timeout)
+    }
+}
+
+pub struct PodcastRefresh {
+    pub title: String,
+    pub status: RefreshStatus,
+    pub moved_to: Option<Url>,
+}
+
+#[derive(Default)]
+pub struct RefreshReport {
+    pub podcasts: Vec<PodcastRefresh>,
+}
+
+impl RefreshReport {
+    pub fn failures(&self) -> impl Iterator<Item = &PodcastRefresh> {
+        self.podcasts.iter().filter(|p| p.status.is_failure())
+    }
+
+    pub fn new_episodes(&self) -> usize {
+        self.podcasts.iter().map(|p| match p.status {
+            RefreshStatus::Updated { new_episodes, .. } => new_episodes,
+            _ => 0,
+        }).sum()
+    }
+
+    pub fn updated_episodes(&self) -> usize {
+        self.podcasts.iter().map(|p| match p.status {
+            RefreshStatus::Updated { updated_episodes, .. } => updated_episodes,
+            _ => 0,
+        }).sum()
+    }
+
+    pub fn withdrawn_episodes(&self) -> usize {
+        self.podcasts.iter().map(|p| match p.status {
+            RefreshStatus::Updated { withdrawn_episodes, .. } => withdrawn_episodes,
+            _ => 0,
+        }).sum()
+    }
+}
+
+
+pub enum FetchedFeed {
+    NotModified {
+        moved_to: Option<Url>,
+    },
+    Modified {
+        content: Vec<u8>,
+        etag: Option<String>,
+        last_modified: Option<String>,
+        moved_to: Option<Url>,
+    },
+}
+
+pub struct FetchMessage {
+    pub id: usize,
+    pub result: Result<FetchedFeed, RefreshStatus>,
+}
+
+pub struct FetchQueueElement {
+    pub id: usize,
+    pub url: Url,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub auth: Option<FeedAuth>,
+}
+
+pub fn create_fetcher(fetch_list: Vec<FetchQueueElement>, options: &RefreshOptions) -> Result<(Receiver<FetchMessage>, Vec<JoinHandle<()>>)> {
+    let threads = options.threads.min(fetch_list.len()).max(1);
+    let fetch_queue: Arc<ArrayQueue<FetchQueueElement>> = Arc::new(ArrayQueue::new(fetch_list.len().max(1)));
+    for e in fetch_list.into_iter() {
+        fetch_queue.push(e).map_err(|_| anyhow!("Failed to create fetch queue"))?;
+    }
+
+    let client = http_client_builder(&options.http)?
+        .timeout(options.timeout.min(options.http.read_timeout()))
+        .redirect(Policy::none())
+        .build()?;
+    let max_redirects = options.http.max_redirects;
+    let (tx, rx) = unbounded::<FetchMessage>();
+    let mut handles: Vec<JoinHandle<()>> = Vec::new();
+
+    for _ in 0..threads {
+        let fetch_queue = fetch_queue.clone();
+        let client = client.clone();
+        let tx = tx.clone();
+        let handle = thread::spawn(move || {
+            while let Some(e) = fetch_queue.pop() {
+                let result = fetch_feed(&client, &e, max_redirects);
+                if tx.send(FetchMessage { id: e.id, result }).is_err() {
+                    break;
+                }
+            }
+        });
+        handles.push(handle);
+    }
+
+    Ok((rx, handles))
+}
+
+/// Follows redirects by hand so that a chain of permanent redirects can be told apart
+/// from a temporary one; only the former should move the subscription.
+fn fetch_feed(client: &Client, e: &FetchQueueElement, max_redirects: usize) -> Result<FetchedFeed, RefreshStatus> {
+    let to_status = |e: reqwest::Error| if e.is_timeout() {
+        RefreshStatus::Timeout
+    } else {
+        RefreshStatus::HttpError(e.to_string())
+    };
+
+    let mut url = e.url.clone();
+    let mut moved_to = None;
+    let mut permanent = true;
+    for _ in 0..=max_redirects {
+        let mut request = client.get(url.clone());
+        // Credentials only go to the host the subscription points at.
+        if let Some(auth) = e.auth.as_ref().filter(|_| url.host_str() == e.url.host_str()) {
+            request = auth.apply(request);
+        }
+        if let Some(etag) = &e.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &e.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        let response = request.send().map_err(to_status)?;
+
+        if response.status().is_redirection() && response.status() != StatusCode::NOT_MODIFIED {
+            let location = response.headers().get(LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|l| url.join(l).ok())
+                .ok_or_else(|| RefreshStatus::HttpError(format!("{} without a valid Location from {url}", response.status())))?;
+            permanent &= matches!(response.status(), StatusCode::MOVED_PERMANENTLY | StatusCode::PERMANENT_REDIRECT);
+            if permanent {
+                moved_to = Some(location.clone());
+            }
+            url = location;
+            continue;
+        }
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(FetchedFeed::NotModified { moved_to });
+        }
+        let response = response.error_for_status().map_err(to_status)?;
+
+        let header_value = |name| response.headers().get(name).and_then(|v| v.to_str().ok()).map(String::from);
+        let etag = header_value(ETAG);
+        let last_modified = header_value(LAST_MODIFIED);
+        let content = response.bytes().map_err(to_status)?.to_vec();
+        return Ok(FetchedFeed::Modified { content, etag, last_modified, moved_to });
+    }
+
+    Err(RefreshStatus::HttpError(format!("Too many redirects from {}", e.url)))
+}
@@ -1,9 +1,15 @@
-use anyhow::Result;
-use reqwest::blocking::{Client, ClientBuilder};
+use std::{fs::{self, File}, io::Write, path::Path};
 
+use anyhow::{Context, Result};
+use reqwest::{blocking::ClientBuilder, redirect::Policy, Certificate, Proxy};
 
+use crate::config::HttpConfig;
+
+
+/// Turns a title into a single path component. Names that would refer to the current or
+/// parent directory (`.`, `..`, or nothing at all) are replaced by underscores.
 pub fn sanitize_filename(name: &str) -> String {
-    name.chars()
+    let name = name.chars()
         .map(|c| match c {
             '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
             c if c.is_control() => '_',
@@ -11,11 +17,77 @@ pub fn sanitize_filename(name: &str) -> String {
         })
         .collect::<String>()
         .trim()
-        .to_string()
+        .to_string();
+    if name.chars().all(|c| c == '.') {
+        return "_".repeat(name.len().max(1));
+    }
+    name
+}
+
+/// FNV-1a, used where a hash has to stay identical across builds and platforms.
+pub fn stable_hash(input: &str) -> u64 {
+    input.bytes().fold(0xcbf29ce484222325, |hash, b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Replaces `path` with `contents` so that a crash leaves either the old or the new file,
+/// never a truncated one.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    let mut file = File::create(&tmp_path)
+        .with_context(|| format!("Failed to create {:?}", tmp_path))?;
+    file.write_all(contents)
+        .and_then(|_| file.sync_all())
+        .with_context(|| format!("Failed to write {:?}", tmp_path))?;
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("Failed to replace {:?}", path))?;
+    sync_parent_dir(path);
+    Ok(())
+}
+
+/// Makes a rename into `path` durable; not every platform can open a directory for this.
+pub fn sync_parent_dir(path: &Path) {
+    if let Some(dir) = path.parent().and_then(|p| File::open(if p.as_os_str().is_empty() { Path::new(".") } else { p }).ok()) {
+        let _ = dir.sync_all();
+    }
+}
+
+
+pub fn http_client_builder(config: &HttpConfig) -> Result<ClientBuilder> {
+    let mut builder = ClientBuilder::new()
+        .user_agent(&config.user_agent)
+        .connect_timeout(config.connect_timeout())
+        .timeout(config.read_timeout())
+        .redirect(Policy::limited(config.max_redirects));
+
+    if let Some(proxy) = &config.proxy {
+        builder = builder.proxy(Proxy::all(proxy).with_context(|| format!("Invalid proxy: {proxy}"))?);
+    }
+    for path in &config.root_certificates {
+        let pem = fs::read(path)
+            .with_context(|| format!("Failed to read certificate: {:?}", path))?;
+        for cert in Certificate::from_pem_bundle(&pem).with_context(|| format!("Invalid certificate: {:?}", path))? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+
+    Ok(builder)
 }
 
 
-pub fn create_reqwest_client() -> Result<Client> {
-    //TODO: make a user agent and headers that doesnt get banned
-    Ok(ClientBuilder::new().build()?)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitized_names_stay_inside_their_directory() {
+        assert_eq!(sanitize_filename(".."), "__");
+        assert_eq!(sanitize_filename(" . "), "_");
+        assert_eq!(sanitize_filename(""), "_");
+        assert_eq!(sanitize_filename("../../etc"), ".._.._etc");
+        assert_eq!(sanitize_filename("a/b: c?"), "a_b_ c_");
+        assert_eq!(sanitize_filename("...And Justice"), "...And Justice");
+    }
 }
+
@@ -1,29 +1,34 @@
 pub mod types;
+pub mod config;
 pub mod helpers;
 pub mod downloader;
+pub mod feed;
+pub mod fetcher;
+pub mod lock;
+pub mod migrations;
+pub mod storage;
 
-use std::{fmt::write, fs};
+use std::fs;
 use std::path::Path;
 use anyhow::{Context, Result};
-use helpers::sanitize_filename;
-use types::PodderDB;
+use fetcher::{RefreshOptions, RefreshReport, WithdrawnPolicy};
+use lock::LibraryLock;
+use storage::load_db;
+use types::{Episode, PodderDB};
 
 pub const DB_FILE_NAME: &str = "podder_db.json";
+pub const SQLITE_DB_FILE_NAME: &str = "podder_db.sqlite";
 pub const PODCAST_DIR: &str = "podcasts";
+pub const BACKUP_DIR: &str = "backups";
+pub const CONFIG_FILE_NAME: &str = "podder_config.json";
+pub const LOCK_FILE_NAME: &str = "podder.lock";
 
-pub fn process_podcasts(base_path: &str) -> Result<PodderDB> {
+/// Takes the library lock without waiting; hold a `LibraryLock` beforehand to wait for it
+/// or to keep it until the returned database has been saved.
+pub fn process_podcasts(base_path: &str, refresh_options: &RefreshOptions) -> Result<(PodderDB, RefreshReport)> {
     let base_path = Path::new(base_path);
-    let db_file_path = base_path.join(DB_FILE_NAME);
-
-    if !db_file_path.exists() {
-        return Err(anyhow::anyhow!("podder_db.json not found at {:?}", db_file_path));
-    }
-
-    let db_content = fs::read_to_string(&db_file_path)
-        .context("Failed to read podder_db.json")?;
-
-    let mut podder_db: PodderDB = serde_json::from_str(&db_content)
-        .context("Failed to parse podder_db.json")?;
+    let _lock = LibraryLock::acquire(base_path, false)?;
+    let (_, mut podder_db) = load_db(base_path)?;
 
     let podcasts_dir = base_path.join(PODCAST_DIR);
     if !podcasts_dir.exists() {
@@ -43,16 +48,27 @@ pub fn process_podcasts(base_path: &str) -> Result<PodderDB> {
         }
     }
 
-    podder_db.update_rss_feeds()
+    let report = podder_db.update_rss_feeds(refresh_options)
         .context("Failed to update RSS feeds")?;
 
 
     for pod in &mut podder_db.podcasts {
         let pod_dir = podcasts_dir.join(pod.filename());
         for episode in &mut pod.episodes {
+            if episode.withdrawn && episode.downloaded_on_last_sync {
+                // The refresh has already happened; one stuck file should not throw it away.
+                if let Err(e) = apply_withdrawn_policy(&pod_dir, episode, refresh_options.withdrawn_policy) {
+                    eprintln!("{:#}", e);
+                }
+            }
             if episode.downloaded_on_last_sync {
                 let episode_file = pod_dir.join(episode.filename());
                 if !episode_file.exists() {
+                    if let Some(previous) = episode.previous_filenames().map(|f| pod_dir.join(f)).find(|f| f.exists()) {
+                        fs::rename(&previous, &episode_file)
+                            .with_context(|| format!("Failed to rename {:?} after a title change", previous))?;
+                        continue;
+                    }
                     episode.downloaded_on_last_sync = false;
                     episode.listened_to = true;
                 }
@@ -62,6 +78,32 @@ pub fn process_podcasts(base_path: &str) -> Result<PodderDB> {
 
     println!("Successfully processed {} podcasts and updated RSS feeds", podder_db.podcasts.len());
 
-    Ok(podder_db)
+    Ok((podder_db, report))
 }
 
+
+fn apply_withdrawn_policy(pod_dir: &Path, episode: &Episode, policy: WithdrawnPolicy) -> Result<()> {
+    let episode_file = pod_dir.join(episode.filename());
+    if !episode_file.exists() {
+        return Ok(());
+    }
+
+    match policy {
+        WithdrawnPolicy::Keep => {},
+        WithdrawnPolicy::Hide => {
+            let hidden_dir = pod_dir.join(".withdrawn");
+            fs::create_dir_all(&hidden_dir)
+                .context("Failed to create directory for withdrawn episodes")?;
+            fs::rename(&episode_file, hidden_dir.join(episode.filename()))
+                .with_context(|| format!("Failed to hide withdrawn episode: {}", episode.title))?;
+            println!("Hid withdrawn episode: {}", episode.title);
+        },
+        WithdrawnPolicy::Delete => {
+            fs::remove_file(&episode_file)
+                .with_context(|| format!("Failed to delete withdrawn episode: {}", episode.title))?;
+            println!("Deleted withdrawn episode: {}", episode.title);
+        },
+    }
+
+    Ok(())
+}
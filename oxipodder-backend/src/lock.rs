@@ -0,0 +1,70 @@
+use std::{fs::{self, File, OpenOptions, TryLockError}, io::Write, path::{Path, PathBuf}, process, sync::{Arc, Mutex, Weak}};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::LOCK_FILE_NAME;
+
+
+/// Locks already held by this process, so nested callers (the CLI and `process_podcasts`)
+/// share one lock instead of blocking on each other.
+static HELD: Mutex<Vec<(PathBuf, Weak<File>)>> = Mutex::new(Vec::new());
+
+/// Exclusive advisory lock on a library directory, released when the last clone is dropped.
+#[derive(Clone)]
+pub struct LibraryLock {
+    _file: Arc<File>,
+}
+
+impl LibraryLock {
+    /// Takes the lock, or fails with a "library busy" error if another process holds it
+    /// and `wait` is false.
+    pub fn acquire(base_path: &Path, wait: bool) -> Result<LibraryLock> {
+        let key = fs::canonicalize(base_path)
+            .with_context(|| format!("Failed to open library directory {:?}", base_path))?;
+
+        let mut held = HELD.lock().map_err(|_| anyhow!("Library lock registry poisoned"))?;
+        held.retain(|(_, file)| file.strong_count() > 0);
+        if let Some(file) = held.iter().find(|(path, _)| *path == key).and_then(|(_, file)| file.upgrade()) {
+            return Ok(LibraryLock { _file: file });
+        }
+
+        let lock_path = key.join(LOCK_FILE_NAME);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)
+            .with_context(|| format!("Failed to open lock file {:?}", lock_path))?;
+
+        match file.try_lock() {
+            Ok(()) => {},
+            Err(TryLockError::WouldBlock) if wait => {
+                println!("Waiting for {} to release {:?}...", holder(&lock_path), base_path);
+                file.lock()
+                    .with_context(|| format!("Failed to lock {:?}", lock_path))?;
+            },
+            Err(TryLockError::WouldBlock) => {
+                return Err(anyhow!("Library {:?} is busy: {} is using it", base_path, holder(&lock_path)));
+            },
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("Failed to lock {:?}", lock_path));
+            },
+        }
+
+        file.set_len(0)
+            .and_then(|_| write!(file, "{}", process::id()))
+            .with_context(|| format!("Failed to write lock file {:?}", lock_path))?;
+
+        let file = Arc::new(file);
+        held.push((key, Arc::downgrade(&file)));
+        Ok(LibraryLock { _file: file })
+    }
+}
+
+fn holder(lock_path: &Path) -> String {
+    match fs::read_to_string(lock_path).ok().and_then(|pid| pid.trim().parse::<u32>().ok()) {
+        Some(pid) => format!("another oxipodder process (pid {pid})"),
+        None => "another oxipodder process".to_string(),
+    }
+}
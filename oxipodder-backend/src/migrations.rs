@@ -0,0 +1,160 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+use crate::types::Episode;
+
+
+/// Version written by this build. Bump it together with a new entry in `MIGRATIONS`.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Each migration upgrades the raw JSON form of the database from `from` to `from + 1`,
+/// before serde ever sees it, and returns how many records it changed.
+struct Migration {
+    from: u32,
+    description: &'static str,
+    apply: fn(&mut Value) -> Result<usize>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration { from: 0, description: "give episodes without a guid a stable identity", apply: assign_missing_guids },
+];
+
+pub struct MigrationStep {
+    pub from: u32,
+    pub to: u32,
+    pub description: &'static str,
+    pub changes: usize,
+}
+
+pub fn schema_version(db: &Value) -> u32 {
+    db.get("schema_version").and_then(Value::as_u64).unwrap_or(0) as u32
+}
+
+/// Brings `db` up to `SCHEMA_VERSION` in place, returning the steps that were applied.
+pub fn migrate(db: &mut Value) -> Result<Vec<MigrationStep>> {
+    let mut version = schema_version(db);
+    if version > SCHEMA_VERSION {
+        return Err(anyhow!("Database uses schema v{version}, but this oxipodder only understands up to v{SCHEMA_VERSION}"));
+    }
+
+    let mut steps = Vec::new();
+    while version < SCHEMA_VERSION {
+        let migration = MIGRATIONS.iter().find(|m| m.from == version)
+            .ok_or_else(|| anyhow!("No migration from schema v{version}"))?;
+        let changes = (migration.apply)(db)
+            .with_context(|| format!("Failed to migrate database from schema v{version}"))?;
+
+        version += 1;
+        db.as_object_mut()
+            .context("Database is not a JSON object")?
+            .insert("schema_version".to_string(), version.into());
+        steps.push(MigrationStep { from: migration.from, to: version, description: migration.description, changes });
+    }
+    Ok(steps)
+}
+
+
+fn episodes_mut(db: &mut Value) -> impl Iterator<Item = &mut Value> {
+    db.get_mut("podcasts")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .filter_map(|p| p.get_mut("episodes").and_then(Value::as_array_mut))
+        .flatten()
+}
+
+/// Hashes the date as stored. Older builds stored 1970 for dates they could not parse, which
+/// the current parser may now read, so `Podcast::merge_feed` also matches these episodes by
+/// their enclosure URL.
+fn assign_missing_guids(db: &mut Value) -> Result<usize> {
+    let mut assigned = 0;
+    for episode in episodes_mut(db) {
+        if episode.get("guid").and_then(Value::as_str).is_some_and(|g| !g.trim().is_empty()) {
+            continue;
+        }
+        let text = |field: &str| episode.pointer(field).and_then(Value::as_str).unwrap_or_default();
+        let pub_date = DateTime::parse_from_rfc3339(text("/pub_date"))
+            .with_context(|| format!("Episode {:?} has an invalid pub_date", text("/title")))?
+            .with_timezone(&Utc);
+        let guid = Episode::fallback_guid(text("/enclosure/url"), text("/title"), &pub_date);
+        episode["guid"] = Value::String(guid);
+        assigned += 1;
+    }
+    Ok(assigned)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::types::PodderDB;
+
+    fn v0_database() -> Value {
+        json!({
+            "podcasts": [{
+                "title": "Old Show",
+                "description": null,
+                "xml_url": "https://example.com/feed.xml",
+                "html_url": null,
+                "auto_download_limit": 5,
+                "episodes": [
+                    {
+                        "guid": "",
+                        "title": "Pilot",
+                        "enclosure": { "url": "https://cdn.example.com/pilot.mp3", "length": 1000, "mime_type": "audio/mpeg" },
+                        "pub_date": "1970-01-01T00:00:00Z",
+                        "downloaded_on_last_sync": true,
+                        "listened_to": false
+                    },
+                    {
+                        "guid": "urn:episode:2",
+                        "title": "Second",
+                        "enclosure": { "url": "https://cdn.example.com/2.mp3", "length": 1000, "mime_type": "audio/mpeg" },
+                        "pub_date": "2024-05-01T10:00:00Z",
+                        "downloaded_on_last_sync": false,
+                        "listened_to": false
+                    }
+                ],
+                "last_refreshed": "2024-05-02T10:00:00Z"
+            }]
+        })
+    }
+
+    #[test]
+    fn v0_episodes_get_fallback_guids() {
+        let mut db = v0_database();
+        let steps = migrate(&mut db).unwrap();
+
+        assert_eq!(steps.len(), 1);
+        assert_eq!((steps[0].from, steps[0].to, steps[0].changes), (0, 1, 1));
+        assert_eq!(schema_version(&db), SCHEMA_VERSION);
+        assert_eq!(
+            db.pointer("/podcasts/0/episodes/0/guid").and_then(Value::as_str).unwrap(),
+            Episode::fallback_guid("https://cdn.example.com/pilot.mp3", "Pilot", &DateTime::UNIX_EPOCH),
+        );
+        assert_eq!(db.pointer("/podcasts/0/episodes/1/guid").and_then(Value::as_str), Some("urn:episode:2"));
+
+        let db: PodderDB = serde_json::from_value(db).unwrap();
+        assert_eq!(db.schema_version, SCHEMA_VERSION);
+        assert!(db.podcasts[0].episodes[0].downloaded_on_last_sync);
+    }
+
+    #[test]
+    fn current_databases_are_left_alone() {
+        let mut db = v0_database();
+        migrate(&mut db).unwrap();
+        let migrated = db.clone();
+
+        assert!(migrate(&mut db).unwrap().is_empty());
+        assert_eq!(db, migrated);
+    }
+
+    #[test]
+    fn newer_schemas_are_rejected() {
+        let mut db = json!({ "schema_version": SCHEMA_VERSION + 1, "podcasts": [] });
+        assert!(migrate(&mut db).is_err());
+    }
+}
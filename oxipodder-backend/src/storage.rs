@@ -0,0 +1,452 @@
+use std::{fmt, fs, path::{Path, PathBuf}, str::FromStr};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use serde_json::Value;
+
+use crate::{config::Config, helpers::write_atomic, migrations::{migrate, schema_version, MigrationStep}, types::PodderDB, BACKUP_DIR, DB_FILE_NAME, SQLITE_DB_FILE_NAME};
+
+
+/// Where a library keeps its `PodderDB`.
+pub trait Storage {
+    fn kind(&self) -> StorageKind;
+    fn location(&self) -> &Path;
+    fn backups(&self) -> &Backups;
+    fn exists(&self) -> bool {
+        self.location().exists()
+    }
+    /// Reads the database as stored, before any schema migration.
+    fn read_raw(&self, path: &Path) -> Result<Value>;
+    fn save(&self, db: &PodderDB) -> Result<()>;
+
+    /// Loads the database, upgrading it to the current schema. The file itself is only
+    /// rewritten on the next save, but the original is backed up first.
+    fn load(&self) -> Result<PodderDB> {
+        let mut db = self.read_raw(self.location())?;
+        let from = schema_version(&db);
+        let steps = migrate(&mut db)?;
+        if !steps.is_empty() {
+            let backup = self.backups().create_pre_migration(self.location(), from)?;
+            // stderr, so that commands printing to stdout (`export`) stay clean.
+            for step in &steps {
+                eprintln!("Upgraded database schema v{} to v{}: {} ({} changed)", step.from, step.to, step.description, step.changes);
+            }
+            eprintln!("The original database is kept at {:?}", backup);
+        }
+        serde_json::from_value(db).with_context(|| format!("Failed to load {:?}", self.location()))
+    }
+
+    /// Reads a database in this storage's format from somewhere other than its usual location.
+    fn load_from(&self, path: &Path) -> Result<PodderDB> {
+        let mut db = self.read_raw(path)?;
+        migrate(&mut db)?;
+        serde_json::from_value(db).with_context(|| format!("Failed to load {:?}", path))
+    }
+
+    /// The migrations loading the database would apply, without changing anything.
+    fn pending_migrations(&self) -> Result<Vec<MigrationStep>> {
+        let mut db = self.read_raw(self.location())?;
+        migrate(&mut db)
+    }
+
+    /// Puts a backup back in place, after backing up the current database.
+    fn restore(&self, backup: &Path) -> Result<()> {
+        if !backup.is_file() {
+            return Err(anyhow!("Backup {:?} does not exist", backup));
+        }
+        self.load_from(backup)
+            .with_context(|| format!("{:?} is not a usable backup", backup))?;
+        let contents = fs::read(backup)
+            .with_context(|| format!("Failed to read {:?}", backup))?;
+        self.backups().create(self.location())?;
+        write_atomic(self.location(), &contents)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StorageKind {
+    Json,
+    Sqlite,
+}
+
+impl FromStr for StorageKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(StorageKind::Json),
+            "sqlite" => Ok(StorageKind::Sqlite),
+            _ => Err(anyhow!("Unknown storage backend: {s}")),
+        }
+    }
+}
+
+impl fmt::Display for StorageKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageKind::Json => write!(f, "json"),
+            StorageKind::Sqlite => write!(f, "sqlite"),
+        }
+    }
+}
+
+pub fn storage(base_path: &Path, kind: StorageKind) -> Result<Box<dyn Storage>> {
+    let keep = Config::load(base_path)?.backups;
+    match kind {
+        StorageKind::Json => Ok(Box::new(JsonStorage::new(
+            base_path.join(DB_FILE_NAME),
+            Backups::new(base_path.join(BACKUP_DIR), DB_FILE_NAME, keep),
+        ))),
+        #[cfg(feature = "sqlite")]
+        StorageKind::Sqlite => Ok(Box::new(sqlite::SqliteStorage::new(
+            base_path.join(SQLITE_DB_FILE_NAME),
+            Backups::new(base_path.join(BACKUP_DIR), SQLITE_DB_FILE_NAME, keep),
+        ))),
+        #[cfg(not(feature = "sqlite"))]
+        StorageKind::Sqlite => Err(anyhow!("oxipodder was built without SQLite support")),
+    }
+}
+
+/// Picks the SQLite database when the library has one, and `podder_db.json` otherwise.
+pub fn open_storage(base_path: &Path) -> Result<Box<dyn Storage>> {
+    if base_path.join(SQLITE_DB_FILE_NAME).exists() {
+        storage(base_path, StorageKind::Sqlite)
+    } else {
+        storage(base_path, StorageKind::Json)
+    }
+}
+
+/// Opens the library's storage and loads it, failing if there is no database yet.
+pub fn load_db(base_path: &Path) -> Result<(Box<dyn Storage>, PodderDB)> {
+    let storage = open_storage(base_path)?;
+    if !storage.exists() {
+        return Err(anyhow!("No podcast database found in {:?}", base_path));
+    }
+    let db = storage.load()?;
+    Ok((storage, db))
+}
+
+
+/// Timestamped copies of a database file, pruned to the newest `keep`.
+pub struct Backups {
+    dir: PathBuf,
+    stem: String,
+    extension: String,
+    keep: usize,
+}
+
+impl Backups {
+    pub fn new(dir: PathBuf, file_name: &str, keep: usize) -> Self {
+        let (stem, extension) = file_name.rsplit_once('.').unwrap_or((file_name, "bak"));
+        Self { dir, stem: stem.to_string(), extension: extension.to_string(), keep }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Copies `source` into the backup directory, if it exists and backups are enabled.
+    pub fn create(&self, source: &Path) -> Result<Option<PathBuf>> {
+        if self.keep == 0 || !source.exists() {
+            return Ok(None);
+        }
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create backup directory {:?}", self.dir))?;
+
+        let name = format!("{}-{}.{}", self.stem, Utc::now().format("%Y%m%d-%H%M%S%.3f"), self.extension);
+        let backup = self.dir.join(name);
+        let contents = fs::read(source)
+            .with_context(|| format!("Failed to read {:?}", source))?;
+        write_atomic(&backup, &contents)?;
+
+        for old in self.list()?.into_iter().skip(self.keep) {
+            fs::remove_file(&old)
+                .with_context(|| format!("Failed to remove old backup {:?}", old))?;
+        }
+        Ok(Some(backup))
+    }
+
+    /// Keeps the file as it was before a schema upgrade. Unlike the rotating backups this is
+    /// never pruned, and an existing copy of the same version is left alone.
+    pub fn create_pre_migration(&self, source: &Path, version: u32) -> Result<PathBuf> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create backup directory {:?}", self.dir))?;
+        let backup = self.dir.join(format!("{}.schema-v{}.{}", self.stem, version, self.extension));
+        if !backup.exists() {
+            let contents = fs::read(source)
+                .with_context(|| format!("Failed to read {:?}", source))?;
+            write_atomic(&backup, &contents)?;
+        }
+        Ok(backup)
+    }
+
+    /// Backups of this database, newest first.
+    pub fn list(&self) -> Result<Vec<PathBuf>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let prefix = format!("{}-", self.stem);
+        let suffix = format!(".{}", self.extension);
+        let mut backups = fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read backup directory {:?}", self.dir))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with(&prefix) && n.ends_with(&suffix)))
+            .collect::<Vec<_>>();
+        backups.sort_by(|a, b| b.cmp(a));
+        Ok(backups)
+    }
+}
+
+
+pub struct JsonStorage {
+    path: PathBuf,
+    backups: Backups,
+}
+
+impl JsonStorage {
+    pub fn new(path: PathBuf, backups: Backups) -> Self {
+        Self { path, backups }
+    }
+}
+
+impl Storage for JsonStorage {
+    fn kind(&self) -> StorageKind {
+        StorageKind::Json
+    }
+
+    fn location(&self) -> &Path {
+        &self.path
+    }
+
+    fn backups(&self) -> &Backups {
+        &self.backups
+    }
+
+    fn read_raw(&self, path: &Path) -> Result<Value> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {:?}", path))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {:?}", path))
+    }
+
+    fn save(&self, db: &PodderDB) -> Result<()> {
+        let content = serde_json::to_string_pretty(db)
+            .context("Failed to serialize database")?;
+        self.backups.create(&self.path)?;
+        write_atomic(&self.path, content.as_bytes())
+    }
+}
+
+
+#[cfg(feature = "sqlite")]
+pub mod sqlite {
+    use std::{collections::HashSet, path::{Path, PathBuf}};
+
+    use anyhow::{anyhow, Context, Result};
+    use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+    use serde_json::Value;
+
+    use crate::types::PodderDB;
+    use super::{Backups, Storage, StorageKind};
+
+    const SCHEMA: &str = "
+        PRAGMA foreign_keys = ON;
+        CREATE TABLE IF NOT EXISTS meta (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS podcasts (
+            xml_url TEXT PRIMARY KEY,
+            position INTEGER NOT NULL,
+            data TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS episodes (
+            podcast_url TEXT NOT NULL REFERENCES podcasts (xml_url) ON DELETE CASCADE,
+            position INTEGER NOT NULL,
+            data TEXT NOT NULL,
+            PRIMARY KEY (podcast_url, position)
+        );
+    ";
+
+    /// Keeps each podcast and episode as its own JSON row, so a save only
+    /// writes the rows that actually changed.
+    pub struct SqliteStorage {
+        path: PathBuf,
+        backups: Backups,
+    }
+
+    impl SqliteStorage {
+        pub fn new(path: PathBuf, backups: Backups) -> Self {
+            Self { path, backups }
+        }
+
+        fn connect(path: &Path) -> Result<Connection> {
+            let conn = Connection::open(path)
+                .with_context(|| format!("Failed to open {:?}", path))?;
+            conn.execute_batch(SCHEMA)
+                .context("Failed to initialise the SQLite schema")?;
+            Ok(conn)
+        }
+
+        /// Opens an existing database without creating it or touching its schema, so that
+        /// checking a backup or a mistyped path leaves the file system as it was.
+        fn connect_read_only(path: &Path) -> Result<Connection> {
+            if !path.is_file() {
+                return Err(anyhow!("{:?} does not exist", path));
+            }
+            Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+                .with_context(|| format!("Failed to open {:?}", path))
+        }
+    }
+
+    impl Storage for SqliteStorage {
+        fn kind(&self) -> StorageKind {
+            StorageKind::Sqlite
+        }
+
+        fn location(&self) -> &Path {
+            &self.path
+        }
+
+        fn backups(&self) -> &Backups {
+            &self.backups
+        }
+
+        fn read_raw(&self, path: &Path) -> Result<Value> {
+            let conn = Self::connect_read_only(path)?;
+
+            let meta: Option<String> = conn
+                .query_row("SELECT value FROM meta WHERE key = 'db'", [], |row| row.get(0))
+                .optional()?;
+            let mut db = match meta {
+                Some(meta) => serde_json::from_str(&meta).context("Failed to parse database metadata")?,
+                None => Value::Object(Default::default()),
+            };
+
+            let mut podcast_rows = conn.prepare("SELECT xml_url, data FROM podcasts ORDER BY position")?;
+            let mut episode_rows = conn.prepare("SELECT data FROM episodes WHERE podcast_url = ?1 ORDER BY position")?;
+            let mut podcasts = Vec::new();
+            for row in podcast_rows.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))? {
+                let (xml_url, data) = row?;
+                let mut podcast: Value = serde_json::from_str(&data)
+                    .with_context(|| format!("Failed to parse podcast {xml_url}"))?;
+                let episodes = episode_rows
+                    .query_map([&xml_url], |row| row.get::<_, String>(0))?
+                    .map(|data| Ok(serde_json::from_str::<Value>(&data?)?))
+                    .collect::<Result<Vec<_>>>()
+                    .with_context(|| format!("Failed to parse episodes of {xml_url}"))?;
+                podcast["episodes"] = Value::Array(episodes);
+                podcasts.push(podcast);
+            }
+            db["podcasts"] = Value::Array(podcasts);
+            Ok(db)
+        }
+
+        fn save(&self, db: &PodderDB) -> Result<()> {
+            self.backups.create(&self.path)?;
+            let mut conn = Self::connect(&self.path)?;
+            let tx = conn.transaction()?;
+
+            let mut meta = serde_json::to_value(db)?;
+            if let Some(fields) = meta.as_object_mut() {
+                fields.remove("podcasts");
+            }
+            tx.execute(
+                "INSERT INTO meta (key, value) VALUES ('db', ?1)
+                 ON CONFLICT (key) DO UPDATE SET value = excluded.value WHERE value <> excluded.value",
+                [meta.to_string()],
+            )?;
+
+            let mut kept = HashSet::new();
+            {
+                let mut upsert_podcast = tx.prepare(
+                    "INSERT INTO podcasts (xml_url, position, data) VALUES (?1, ?2, ?3)
+                     ON CONFLICT (xml_url) DO UPDATE SET position = excluded.position, data = excluded.data
+                     WHERE position <> excluded.position OR data <> excluded.data",
+                )?;
+                let mut upsert_episode = tx.prepare(
+                    "INSERT INTO episodes (podcast_url, position, data) VALUES (?1, ?2, ?3)
+                     ON CONFLICT (podcast_url, position) DO UPDATE SET data = excluded.data
+                     WHERE data <> excluded.data",
+                )?;
+                let mut trim_episodes = tx.prepare("DELETE FROM episodes WHERE podcast_url = ?1 AND position >= ?2")?;
+
+                for (position, podcast) in db.podcasts.iter().enumerate() {
+                    let xml_url = podcast.xml_url.as_str();
+                    if !kept.insert(xml_url) {
+                        return Err(anyhow!("More than one podcast uses the feed {xml_url}"));
+                    }
+
+                    let mut data = serde_json::to_value(podcast)?;
+                    if let Some(fields) = data.as_object_mut() {
+                        fields.remove("episodes");
+                    }
+                    upsert_podcast.execute(params![xml_url, position, data.to_string()])?;
+
+                    for (position, episode) in podcast.episodes.iter().enumerate() {
+                        upsert_episode.execute(params![xml_url, position, serde_json::to_string(episode)?])?;
+                    }
+                    trim_episodes.execute(params![xml_url, podcast.episodes.len()])?;
+                }
+
+                let stored = tx.prepare("SELECT xml_url FROM podcasts")?
+                    .query_map([], |row| row.get::<_, String>(0))?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                for xml_url in stored.iter().filter(|u| !kept.contains(u.as_str())) {
+                    tx.execute("DELETE FROM podcasts WHERE xml_url = ?1", [xml_url])?;
+                }
+            }
+
+            tx.commit().with_context(|| format!("Failed to write {:?}", self.path))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+
+    fn temp_library(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("oxipodder-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn restoring_a_missing_backup_fails_without_creating_it() {
+        let dir = temp_library("json-restore");
+        let storage = storage(&dir, StorageKind::Json).unwrap();
+        storage.save(&PodderDB::default()).unwrap();
+
+        let missing = storage.backups().dir().join("typo.json");
+        assert!(storage.restore(&missing).is_err());
+        assert!(!missing.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_backups_are_read_without_being_created_or_changed() {
+        let dir = temp_library("sqlite-restore");
+        let storage = storage(&dir, StorageKind::Sqlite).unwrap();
+        storage.save(&PodderDB::default()).unwrap();
+        storage.save(&PodderDB::default()).unwrap();
+
+        let missing = storage.backups().dir().join("typo.sqlite");
+        assert!(storage.restore(&missing).is_err());
+        assert!(!missing.exists());
+
+        let backup = storage.backups().list().unwrap().into_iter().next().unwrap();
+        let before = fs::read(&backup).unwrap();
+        storage.load_from(&backup).unwrap();
+        assert_eq!(fs::read(&backup).unwrap(), before);
+        storage.restore(&backup).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -3,44 +3,93 @@ use std::{collections::HashMap, thread::JoinHandle};
 use anyhow::Result;
 use crossbeam::channel::Receiver;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use oxipodder_backend::downloader::DownloadMessage;
+use oxipodder_backend::downloader::{DownloadFailure, DownloadMessage};
 
 
+/// `total_size` is 0 when the server did not say how big the file is.
 fn create_task_text(total_size: u64, completed: u64, name: &str) -> String {
     let com_mb = completed as f64 / 1048576.0;
+    if total_size == 0 {
+        return format!("{com_mb:.1} MB - {name}");
+    }
     let tot_mb = total_size as f64 / 1048576.0;
     format!("{com_mb:.1} / {tot_mb:.1} MB - {name}")
 }
 
-pub fn create_download_view(rx: Receiver<DownloadMessage>, handles: Vec<JoinHandle<()>>, display_texts: Vec<String>) -> Result<()> {
-    let mut mb = MultiProgress::new();
+fn progress_style(total_size: u64) -> ProgressStyle {
+    // Chunked responses have no length to measure progress against.
+    let template = if total_size == 0 {
+        "{spinner:.green} {msg}"
+    } else {
+        "{spinner:.green} [{bar:40.cyan/blue}] {pos:>7}/{len:7} {msg}"
+    };
+    ProgressStyle::default_bar()
+        .template(template)
+        .unwrap()
+        .progress_chars("#>-")
+}
+
+#[derive(Default)]
+pub struct DownloadResults {
+    pub completed: Vec<u32>,
+    pub failed: Vec<DownloadFailure>,
+}
+
+/// Shows progress until the downloader is done and returns which downloads finished or failed.
+pub fn create_download_view(rx: Receiver<DownloadMessage>, _handles: Vec<JoinHandle<()>>, display_texts: Vec<String>) -> Result<DownloadResults> {
+    let mb = MultiProgress::new();
     let mut bars: HashMap<u32, ProgressBar> = HashMap::new();
+    let mut results = DownloadResults::default();
     while let Ok(msg) = rx.recv() {
         match msg {
             DownloadMessage::Started(dp) => {
-                let pb: ProgressBar = ProgressBar::new(100);
-                pb.set_style(
-                ProgressStyle::default_bar()
-                    .template("{spinner:.green} [{bar:40.cyan/blue}] {pos:>7}/{len:7} {msg}")
-                    .unwrap()
-                    .progress_chars("#>-")
-                );
-                pb.set_message(create_task_text(dp.total_size, dp.completed, &*display_texts.get(dp.id as usize).unwrap_or(&"".to_string())));
-                bars.insert(dp.id, mb.add(pb));
+                // A retried download starts again on the bar it already has.
+                let pb = bars.entry(dp.id).or_insert_with(|| mb.add(ProgressBar::new(100)));
+                pb.set_style(progress_style(dp.total_size));
+                pb.set_message(create_task_text(dp.total_size, dp.completed, display_texts.get(dp.id as usize).unwrap_or(&"".to_string())));
             },
             DownloadMessage::Incremental(dp) => {
                 let pb = bars.get(&dp.id).unwrap();
-                let percent: u64 = 100 * dp.completed / dp.total_size;
-                pb.set_position(percent);
-                pb.set_message(create_task_text(dp.total_size, dp.completed, &*display_texts.get(dp.id as usize).unwrap_or(&"".to_string())));
+                match (100 * dp.completed).checked_div(dp.total_size) {
+                    Some(percent) => pb.set_position(percent),
+                    None => pb.tick(),
+                }
+                pb.set_message(create_task_text(dp.total_size, dp.completed, display_texts.get(dp.id as usize).unwrap_or(&"".to_string())));
             },
             DownloadMessage::Completed(dp) => {
                 let pb = bars.get(&dp.id).unwrap();
-                pb.finish_with_message(format!("Downloaded {}", *display_texts.get(dp.id as usize).unwrap_or(&"".to_string())));
+                pb.finish_with_message(format!("Downloaded {}", display_texts.get(dp.id as usize).unwrap_or(&"".to_string())));
+                results.completed.push(dp.id);
+            },
+            DownloadMessage::Retrying(retry) => {
+                let text = format!(
+                    "Retrying {} ({}/{}) in {:.1}s: {}",
+                    display_texts.get(retry.id as usize).unwrap_or(&"".to_string()),
+                    retry.attempt,
+                    retry.max_attempts,
+                    retry.delay.as_secs_f32(),
+                    retry.reason,
+                );
+                match bars.get(&retry.id) {
+                    Some(pb) => pb.set_message(text),
+                    None => { let _ = mb.println(text); },
+                }
+            },
+            DownloadMessage::Failed(failure) => {
+                let text = format!(
+                    "Failed {}: {}: {}",
+                    display_texts.get(failure.id as usize).unwrap_or(&"".to_string()),
+                    failure.kind,
+                    failure.message,
+                );
+                match bars.get(&failure.id) {
+                    Some(pb) => pb.abandon_with_message(text),
+                    None => { let _ = mb.println(text); },
+                }
+                results.failed.push(failure);
             },
-            DownloadMessage::Failed(_) => todo!(),
             DownloadMessage::ThreadTerminated => {},
         };
     }
-    Ok(())
+    Ok(results)
 }
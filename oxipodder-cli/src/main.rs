@@ -1,19 +1,23 @@
 mod download_view;
 
-use anyhow::{Context, Result};
-use download_view::create_download_view;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use clap::{Arg, Command};
+use anyhow::{anyhow, Context, Result};
+use download_view::{create_download_view, DownloadResults};
+use clap::{Arg, ArgMatches, Command};
 use opml::OPML;
-use oxipodder_backend::downloader::{create_downloader, DownloadMessage, DownloadQueueElement};
-use oxipodder_backend::process_podcasts;
-use oxipodder_backend::types::PodderDB;
-use reqwest::blocking::Client;
-use reqwest::IntoUrl;
-use std::fs;
+use reqwest::header::{HeaderName, HeaderValue};
+use oxipodder_backend::config::{Config, HttpConfig};
+use oxipodder_backend::downloader::{create_downloader, DownloadErrorKind, DownloadKind, DownloadQueueElement};
+use oxipodder_backend::fetcher::{RefreshOptions, RefreshReport};
+use oxipodder_backend::lock::LibraryLock;
+use oxipodder_backend::migrations::SCHEMA_VERSION;
+use oxipodder_backend::{process_podcasts, PODCAST_DIR};
+use oxipodder_backend::storage::{load_db, open_storage, storage, StorageKind};
+use oxipodder_backend::types::{BasicAuth, FeedAuth, PodderDB};
+use std::collections::HashMap;
+use std::{env, fs, io};
 use std::path::{Path, PathBuf};
-use std::io::Write;
 use std::str::FromStr;
+use std::time::Duration;
 use url::Url;
 
 fn main() -> Result<()> {
@@ -21,6 +25,63 @@ fn main() -> Result<()> {
         .version("0.1.0")
         .author("Your Name")
         .about("A podcast downloader and manager")
+        .arg(
+            Arg::new("user-agent")
+                .long("user-agent")
+                .value_name("AGENT")
+                .help("User agent sent with every request")
+                .global(true),
+        )
+        .arg(
+            Arg::new("proxy")
+                .long("proxy")
+                .value_name("URL")
+                .help("HTTP(S) or SOCKS5 proxy, e.g. socks5://127.0.0.1:9050")
+                .global(true),
+        )
+        .arg(
+            Arg::new("connect-timeout")
+                .long("connect-timeout")
+                .value_name("SECONDS")
+                .help("Timeout for establishing connections")
+                .global(true),
+        )
+        .arg(
+            Arg::new("read-timeout")
+                .long("read-timeout")
+                .value_name("SECONDS")
+                .help("Timeout for a stalled read")
+                .global(true),
+        )
+        .arg(
+            Arg::new("ca-cert")
+                .long("ca-cert")
+                .value_name("FILE")
+                .help("Extra PEM certificate authority to trust")
+                .action(clap::ArgAction::Append)
+                .global(true),
+        )
+        .arg(
+            Arg::new("max-redirects")
+                .long("max-redirects")
+                .value_name("NUMBER")
+                .help("Maximum number of redirects to follow")
+                .global(true),
+        )
+        .arg(
+            Arg::new("retries")
+                .long("retries")
+                .value_name("NUMBER")
+                .help("How many times to retry a failed download")
+                .global(true),
+        )
+        .arg(
+            Arg::new("wait")
+                .long("wait")
+                .help("Wait for another oxipodder process to finish with the library instead of failing")
+                .action(clap::ArgAction::SetTrue)
+                .global(true),
+        )
         .subcommand(
             Command::new("create")
                 .about("Create a new podcast database from OPML file")
@@ -55,6 +116,35 @@ fn main() -> Result<()> {
                         .value_name("NUMBER")
                         .help("Set auto download limit for each podcast")
                         .default_value("5"),
+                )
+                .arg(
+                    Arg::new("storage")
+                        .long("storage")
+                        .value_name("BACKEND")
+                        .help("Where to keep the database")
+                        .value_parser(["json", "sqlite"])
+                        .default_value("json"),
+                )
+                .arg(
+                    Arg::new("sidecars")
+                        .long("sidecars")
+                        .help("Also download transcripts and chapters next to the audio")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("threads")
+                        .long("threads")
+                        .short('t')
+                        .value_name("NUMBER")
+                        .help("Number of feeds to fetch concurrently")
+                        .default_value("8"),
+                )
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .help("Timeout for each feed request")
+                        .default_value("30"),
                 ),
         )
         .subcommand(
@@ -74,6 +164,48 @@ fn main() -> Result<()> {
                         .short('d')
                         .help("Download new episodes after updating feeds")
                         .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("sidecars")
+                        .long("sidecars")
+                        .help("Also download transcripts and chapters next to the audio")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("redownload-changed")
+                        .long("redownload-changed")
+                        .help("Download episodes again when the feed moves their audio to a new URL")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("withdrawn")
+                        .long("withdrawn")
+                        .value_name("POLICY")
+                        .help("What to do with downloaded episodes that were removed from their feed")
+                        .value_parser(["keep", "hide", "delete"])
+                        .default_value("keep"),
+                )
+                .arg(
+                    Arg::new("withdrawn-after")
+                        .long("withdrawn-after")
+                        .value_name("DAYS")
+                        .help("How long an episode must be missing from its feed before it counts as withdrawn")
+                        .default_value("7"),
+                )
+                .arg(
+                    Arg::new("threads")
+                        .long("threads")
+                        .short('t')
+                        .value_name("NUMBER")
+                        .help("Number of feeds to fetch concurrently")
+                        .default_value("8"),
+                )
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .help("Timeout for each feed request")
+                        .default_value("30"),
                 ),
         )
         .subcommand(
@@ -94,6 +226,187 @@ fn main() -> Result<()> {
                         .value_name("NUMBER")
                         .help("Number of episodes to download per podcast")
                         .default_value("5"),
+                )
+                .arg(
+                    Arg::new("sidecars")
+                        .long("sidecars")
+                        .help("Also download transcripts and chapters next to the audio")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Add the podcasts from an OPML file to an existing database")
+                .arg(
+                    Arg::new("opml")
+                        .long("opml")
+                        .short('o')
+                        .value_name("FILE")
+                        .help("Path to OPML file containing podcast subscriptions")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("path")
+                        .long("path")
+                        .short('p')
+                        .value_name("DIR")
+                        .help("Path to podcast database directory")
+                        .default_value("."),
+                ),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Export subscriptions from the database")
+                .arg(
+                    Arg::new("opml")
+                        .long("opml")
+                        .short('o')
+                        .value_name("FILE")
+                        .help("Write an OPML file, or to stdout when no file is given")
+                        .num_args(0..=1)
+                        .default_missing_value("-")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("path")
+                        .long("path")
+                        .short('p')
+                        .value_name("DIR")
+                        .help("Path to podcast database directory")
+                        .default_value("."),
+                )
+                .arg(
+                    Arg::new("tags")
+                        .long("tags")
+                        .help("Group podcasts into folders by their tags")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("add")
+                .about("Subscribe to a podcast feed")
+                .arg(
+                    Arg::new("url")
+                        .value_name("FEED_URL")
+                        .help("URL of the RSS or Atom feed")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("path")
+                        .long("path")
+                        .short('p')
+                        .value_name("DIR")
+                        .help("Path to podcast database directory")
+                        .default_value("."),
+                )
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .help("Timeout for the feed request")
+                        .default_value("30"),
+                )
+                .args(auth_args()),
+        )
+        .subcommand(
+            Command::new("remove")
+                .about("Unsubscribe from a podcast")
+                .arg(
+                    Arg::new("podcast")
+                        .value_name("PODCAST")
+                        .help("Title or feed URL of the podcast")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("path")
+                        .long("path")
+                        .short('p')
+                        .value_name("DIR")
+                        .help("Path to podcast database directory")
+                        .default_value("."),
+                )
+                .arg(
+                    Arg::new("delete-files")
+                        .long("delete-files")
+                        .help("Also delete the podcast's downloaded episodes instead of keeping them")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("migrate")
+                .about("Upgrade the database schema, or move the database to another storage backend")
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .value_name("BACKEND")
+                        .help("Storage backend to convert the database to")
+                        .value_parser(["json", "sqlite"]),
+                )
+                .arg(
+                    Arg::new("check")
+                        .long("check")
+                        .help("Only report which schema upgrades would be applied")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("to"),
+                )
+                .arg(
+                    Arg::new("path")
+                        .long("path")
+                        .short('p')
+                        .value_name("DIR")
+                        .help("Path to podcast database directory")
+                        .default_value("."),
+                ),
+        )
+        .subcommand(
+            Command::new("restore")
+                .about("Roll the database back to a backup")
+                .arg(
+                    Arg::new("backup")
+                        .value_name("BACKUP")
+                        .help("Backup file to restore, defaults to the newest one"),
+                )
+                .arg(
+                    Arg::new("path")
+                        .long("path")
+                        .short('p')
+                        .value_name("DIR")
+                        .help("Path to podcast database directory")
+                        .default_value("."),
+                )
+                .arg(
+                    Arg::new("list")
+                        .long("list")
+                        .short('l')
+                        .help("List the available backups instead of restoring")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("backup"),
+                ),
+        )
+        .subcommand(
+            Command::new("auth")
+                .about("Set credentials for a private feed")
+                .arg(
+                    Arg::new("podcast")
+                        .value_name("PODCAST")
+                        .help("Title or feed URL of the podcast")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("path")
+                        .long("path")
+                        .short('p')
+                        .value_name("DIR")
+                        .help("Path to podcast database directory")
+                        .default_value("."),
+                )
+                .args(auth_args())
+                .arg(
+                    Arg::new("clear")
+                        .long("clear")
+                        .help("Remove all credentials from the podcast")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with_all(["username", "header", "query"]),
                 ),
         )
         .get_matches();
@@ -113,13 +426,39 @@ fn main() -> Result<()> {
                 .parse()
                 .context("Invalid auto download limit")?;
 
-            create_podderdb_from_opml(opml_path, output_dir, episodes_count, auto_download_limit)?;
+            let with_sidecars = sub_matches.get_flag("sidecars");
+            let http = http_config_from_matches(output_dir, sub_matches)?;
+            let refresh_options = refresh_options_from_matches(sub_matches, http)?;
+
+            let storage_kind = sub_matches.get_one::<String>("storage").unwrap().parse()?;
+
+            fs::create_dir_all(output_dir)
+                .with_context(|| format!("Failed to create output directory: {}", output_dir))?;
+            let _lock = lock_library(output_dir, sub_matches)?;
+
+            create_podderdb_from_opml(opml_path, output_dir, episodes_count, auto_download_limit, with_sidecars, storage_kind, &refresh_options)?;
         }
         Some(("update", sub_matches)) => {
             let path = sub_matches.get_one::<String>("path").unwrap();
             let should_download = sub_matches.get_flag("download");
+            let with_sidecars = sub_matches.get_flag("sidecars");
 
-            update_podderdb(path, should_download)?;
+            let http = http_config_from_matches(path, sub_matches)?;
+            let mut refresh_options = refresh_options_from_matches(sub_matches, http)?;
+            refresh_options.redownload_on_enclosure_change = sub_matches.get_flag("redownload-changed");
+            refresh_options.withdrawn_policy = sub_matches.get_one::<String>("withdrawn").unwrap().parse()?;
+            let withdrawn_after: u64 = sub_matches
+                .get_one::<String>("withdrawn-after")
+                .unwrap()
+                .parse()
+                .context("Invalid number of days")?;
+            refresh_options.withdrawn_grace = withdrawn_after.checked_mul(24 * 60 * 60)
+                .map(Duration::from_secs)
+                .context("Too many days for --withdrawn-after")?;
+
+            let _lock = lock_library(path, sub_matches)?;
+
+            update_podderdb(path, should_download, with_sidecars, &refresh_options)?;
         }
         Some(("download", sub_matches)) => {
             let path = sub_matches.get_one::<String>("path").unwrap();
@@ -128,8 +467,94 @@ fn main() -> Result<()> {
                 .unwrap()
                 .parse()
                 .context("Invalid episodes number")?;
+            let with_sidecars = sub_matches.get_flag("sidecars");
+            let http = http_config_from_matches(path, sub_matches)?;
+
+            let _lock = lock_library(path, sub_matches)?;
+            download_episodes(path, episodes_count, with_sidecars, &http)?;
+        }
+        Some(("import", sub_matches)) => {
+            let opml_path = sub_matches.get_one::<String>("opml").unwrap();
+            let path = sub_matches.get_one::<String>("path").unwrap();
 
-            download_episodes(path, episodes_count)?;
+            fs::create_dir_all(path)
+                .with_context(|| format!("Failed to create output directory: {}", path))?;
+            let _lock = lock_library(path, sub_matches)?;
+            import_opml(opml_path, path)?;
+        }
+        Some(("export", sub_matches)) => {
+            let opml_path = sub_matches.get_one::<String>("opml").unwrap();
+            let path = sub_matches.get_one::<String>("path").unwrap();
+            let with_tags = sub_matches.get_flag("tags");
+
+            export_opml(path, opml_path, with_tags)?;
+        }
+        Some(("add", sub_matches)) => {
+            let path = sub_matches.get_one::<String>("path").unwrap();
+            let url = sub_matches.get_one::<String>("url").unwrap();
+            let mut url = Url::parse(url).with_context(|| format!("Invalid feed URL: {url}"))?;
+            let timeout: u64 = sub_matches
+                .get_one::<String>("timeout")
+                .unwrap()
+                .parse()
+                .context("Invalid timeout")?;
+
+            let http = http_config_from_matches(path, sub_matches)?;
+            let refresh_options = RefreshOptions { timeout: Duration::from_secs(timeout), http, ..Default::default() };
+            let mut auth = feed_auth_from_matches(sub_matches)?;
+            auth.take_query_from(&mut url, &url_query_keys(sub_matches))?;
+            let auth = Some(auth).filter(|a| !a.is_empty());
+
+            fs::create_dir_all(path)
+                .with_context(|| format!("Failed to create output directory: {}", path))?;
+            let _lock = lock_library(path, sub_matches)?;
+            add_podcast(path, url, auth, &refresh_options)?;
+        }
+        Some(("remove", sub_matches)) => {
+            let path = sub_matches.get_one::<String>("path").unwrap();
+            let podcast = sub_matches.get_one::<String>("podcast").unwrap();
+            let delete_files = sub_matches.get_flag("delete-files");
+
+            let _lock = lock_library(path, sub_matches)?;
+            remove_podcast(path, podcast, delete_files)?;
+        }
+        Some(("migrate", sub_matches)) => {
+            let path = sub_matches.get_one::<String>("path").unwrap();
+            let target = sub_matches.get_one::<String>("to").map(|t| t.parse()).transpose()?;
+
+            if sub_matches.get_flag("check") {
+                check_schema(path)?;
+            } else {
+                let _lock = lock_library(path, sub_matches)?;
+                match target {
+                    Some(target) => migrate_storage(path, target)?,
+                    None => upgrade_schema(path)?,
+                }
+            }
+        }
+        Some(("restore", sub_matches)) => {
+            let path = sub_matches.get_one::<String>("path").unwrap();
+            let backup = sub_matches.get_one::<String>("backup");
+
+            if sub_matches.get_flag("list") {
+                list_backups(path)?;
+            } else {
+                let _lock = lock_library(path, sub_matches)?;
+                restore_backup(path, backup.map(String::as_str))?;
+            }
+        }
+        Some(("auth", sub_matches)) => {
+            let path = sub_matches.get_one::<String>("path").unwrap();
+            let podcast = sub_matches.get_one::<String>("podcast").unwrap();
+
+            let auth = if sub_matches.get_flag("clear") {
+                None
+            } else {
+                Some(feed_auth_from_matches(sub_matches)?)
+            };
+
+            let _lock = lock_library(path, sub_matches)?;
+            set_podcast_auth(path, podcast, auth, &url_query_keys(sub_matches))?;
         }
         _ => {
             println!("No subcommand provided. Use --help for usage information.");
@@ -139,11 +564,139 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Credential flags shared by `auth` and `add`.
+fn auth_args() -> [Arg; 5] {
+    [
+        Arg::new("username")
+            .long("username")
+            .short('u')
+            .value_name("USER")
+            .help("Username for HTTP basic auth"),
+        Arg::new("password")
+            .long("password")
+            .value_name("PASSWORD")
+            .help("Password for HTTP basic auth; prefer --password-stdin or $OXIPODDER_PASSWORD, which stay out of shell history")
+            .requires("username"),
+        Arg::new("password-stdin")
+            .long("password-stdin")
+            .help("Read the password for HTTP basic auth from the first line of stdin")
+            .action(clap::ArgAction::SetTrue)
+            .requires("username")
+            .conflicts_with("password"),
+        Arg::new("header")
+            .long("header")
+            .short('H')
+            .value_name("NAME: VALUE")
+            .help("Extra header to send with feed and episode requests")
+            .action(clap::ArgAction::Append),
+        Arg::new("query")
+            .long("query")
+            .value_name("KEY[=VALUE]")
+            .help("Query parameter to append to feed and episode requests, e.g. a feed token. A bare KEY moves that parameter out of the feed URL. Either way it is left out of exported OPML")
+            .action(clap::ArgAction::Append),
+    ]
+}
+
+/// Starts from `podder_config.json` in the library directory and applies command line overrides.
+fn http_config_from_matches(base_dir: &str, matches: &ArgMatches) -> Result<HttpConfig> {
+    let mut http = Config::load(Path::new(base_dir))?.http;
+
+    if let Some(user_agent) = matches.get_one::<String>("user-agent") {
+        http.user_agent = user_agent.clone();
+    }
+    if let Some(proxy) = matches.get_one::<String>("proxy") {
+        http.proxy = Some(proxy.clone());
+    }
+    if let Some(timeout) = matches.get_one::<String>("connect-timeout") {
+        http.connect_timeout_secs = timeout.parse().context("Invalid connect timeout")?;
+    }
+    if let Some(timeout) = matches.get_one::<String>("read-timeout") {
+        http.read_timeout_secs = timeout.parse().context("Invalid read timeout")?;
+    }
+    if let Some(certs) = matches.get_many::<String>("ca-cert") {
+        http.root_certificates.extend(certs.map(PathBuf::from));
+    }
+    if let Some(max_redirects) = matches.get_one::<String>("max-redirects") {
+        http.max_redirects = max_redirects.parse().context("Invalid number of redirects")?;
+    }
+    if let Some(retries) = matches.get_one::<String>("retries") {
+        http.download_retries = retries.parse().context("Invalid number of retries")?;
+    }
+
+    Ok(http)
+}
+
+fn feed_auth_from_matches(matches: &ArgMatches) -> Result<FeedAuth> {
+    let basic = match matches.get_one::<String>("username") {
+        Some(username) => Some(BasicAuth { username: username.clone(), password: read_password(matches)? }),
+        None => None,
+    };
+    let headers = matches.get_many::<String>("header").unwrap_or_default()
+        .map(|h| {
+            let (name, value) = h.split_once(':')
+                .map(|(name, value)| (name.trim(), value.trim()))
+                .with_context(|| format!("Invalid header, expected NAME: VALUE: {h}"))?;
+            HeaderName::from_bytes(name.as_bytes()).with_context(|| format!("Invalid header name: {name:?}"))?;
+            HeaderValue::from_str(value).with_context(|| format!("Invalid value for header {name}"))?;
+            Ok((name.to_string(), value.to_string()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let query = matches.get_many::<String>("query").unwrap_or_default()
+        .filter_map(|q| q.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    Ok(FeedAuth { basic, headers, query })
+}
+
+/// Bare `--query KEY` arguments, naming parameters to move out of the feed URL.
+fn url_query_keys(matches: &ArgMatches) -> Vec<String> {
+    matches.get_many::<String>("query").unwrap_or_default()
+        .filter(|q| !q.contains('='))
+        .cloned()
+        .collect()
+}
+
+/// The basic auth password from stdin, the command line or `OXIPODDER_PASSWORD`, in that order.
+fn read_password(matches: &ArgMatches) -> Result<Option<String>> {
+    if matches.get_flag("password-stdin") {
+        let mut password = String::new();
+        io::stdin().read_line(&mut password).context("Failed to read the password from stdin")?;
+        return Ok(Some(password.trim_end_matches(['\r', '\n']).to_string()));
+    }
+    if let Some(password) = matches.get_one::<String>("password") {
+        return Ok(Some(password.clone()));
+    }
+    Ok(env::var("OXIPODDER_PASSWORD").ok())
+}
+
+/// Held for the whole of every command that changes the database or the podcast directories.
+fn lock_library(path: &str, matches: &ArgMatches) -> Result<LibraryLock> {
+    LibraryLock::acquire(Path::new(path), matches.get_flag("wait"))
+}
+
+fn refresh_options_from_matches(matches: &ArgMatches, http: HttpConfig) -> Result<RefreshOptions> {
+    let threads: usize = matches
+        .get_one::<String>("threads")
+        .unwrap()
+        .parse()
+        .context("Invalid number of threads")?;
+    let timeout: u64 = matches
+        .get_one::<String>("timeout")
+        .unwrap()
+        .parse()
+        .context("Invalid timeout")?;
+
+    Ok(RefreshOptions { threads, timeout: Duration::from_secs(timeout), http, ..Default::default() })
+}
+
 fn create_podderdb_from_opml(
     opml_path: &str,
     output_dir: &str,
     episodes_count: usize,
     auto_download_limit: i32,
+    with_sidecars: bool,
+    storage_kind: StorageKind,
+    refresh_options: &RefreshOptions,
 ) -> Result<()> {
     println!("Creating podcast database from OPML file: {}", opml_path);
 
@@ -167,8 +720,9 @@ fn create_podderdb_from_opml(
         .with_context(|| format!("Failed to create output directory: {}", output_dir))?;
 
     println!("Updating RSS feeds...");
-    podder_db.update_rss_feeds()
+    let report = podder_db.update_rss_feeds(refresh_options)
         .context("Failed to update RSS feeds")?;
+    print_refresh_report(&report);
 
     let podcasts_dir = output_path.join("podcasts");
     fs::create_dir_all(&podcasts_dir)
@@ -186,142 +740,431 @@ fn create_podderdb_from_opml(
     }
 
     // Save the database
-    let db_file_path = output_path.join("podder_db.json");
-    let db_content = serde_json::to_string_pretty(&podder_db)
-        .context("Failed to serialize database")?;
-
-    fs::write(&db_file_path, db_content)
-        .with_context(|| format!("Failed to write database file: {:?}", db_file_path))?;
+    let storage = storage(output_path, storage_kind)?;
+    storage.save(&podder_db)?;
 
-    println!("Database created successfully at: {:?}", db_file_path);
+    println!("Database created successfully at: {:?}", storage.location());
 
     // Download episodes
     if episodes_count > 0 {
         println!("Downloading {} episodes per podcast...", episodes_count);
-        download_episodes_from_db(&mut podder_db, &podcasts_dir, episodes_count)?;
+        download_episodes_from_db(&mut podder_db, &podcasts_dir, episodes_count, with_sidecars, &refresh_options.http)?;
 
         // Save updated database with download status
-        let updated_db_content = serde_json::to_string_pretty(&podder_db)
-            .context("Failed to serialize updated database")?;
-
-        fs::write(&db_file_path, updated_db_content)
-            .context("Failed to save updated database")?;
+        storage.save(&podder_db)?;
     }
 
     println!("Podcast database created successfully!");
     Ok(())
 }
 
-fn update_podderdb(path: &str, should_download: bool) -> Result<()> {
+fn update_podderdb(path: &str, should_download: bool, with_sidecars: bool, refresh_options: &RefreshOptions) -> Result<()> {
     println!("Updating podcast database at: {}", path);
 
     let base_path = Path::new(path);
-    let mut podder_db = process_podcasts(path)?;
+    let (mut podder_db, report) = process_podcasts(path, refresh_options)?;
 
-    println!("RSS feeds updated successfully!");
+    print_refresh_report(&report);
 
     if should_download {
         let podcasts_dir = base_path.join("podcasts");
         let episodes_count = 5; // Default download count
 
         println!("Downloading new episodes...");
-        download_episodes_from_db(&mut podder_db, &podcasts_dir, episodes_count)?;
+        download_episodes_from_db(&mut podder_db, &podcasts_dir, episodes_count, with_sidecars, &refresh_options.http)?;
 
     }
 
-    let final_db_content = serde_json::to_string_pretty(&podder_db)
-        .context("Failed to serialize final database")?;
+    open_storage(base_path)?.save(&podder_db)?;
 
-    fs::write(base_path.join("podder_db.json"), final_db_content)
-        .context("Failed to save final database")?;
+    Ok(())
+}
 
+fn print_refresh_report(report: &RefreshReport) {
+    for podcast in &report.podcasts {
+        let marker = if podcast.status.is_failure() { "!" } else { "-" };
+        println!("{marker} {}: {}", podcast.title, podcast.status);
+        if let Some(url) = &podcast.moved_to {
+            println!("  feed moved to {url}");
+        }
+    }
 
-    Ok(())
+    let failures = report.failures().count();
+    println!(
+        "Refreshed {} podcasts: {} new episodes, {} updated, {} withdrawn, {} failed",
+        report.podcasts.len(),
+        report.new_episodes(),
+        report.updated_episodes(),
+        report.withdrawn_episodes(),
+        failures,
+    );
 }
 
-fn download_episodes(path: &str, episodes_count: usize) -> Result<()> {
+fn download_episodes(path: &str, episodes_count: usize, with_sidecars: bool, http: &HttpConfig) -> Result<()> {
     println!("Downloading episodes from database at: {}", path);
 
     let base_path = Path::new(path);
-    let db_file_path = base_path.join("podder_db.json");
+    let (storage, mut podder_db) = load_db(base_path)?;
 
-    if !db_file_path.exists() {
-        return Err(anyhow::anyhow!("podder_db.json not found at {:?}", db_file_path));
-    }
+    let podcasts_dir = base_path.join("podcasts");
+
+    download_episodes_from_db(&mut podder_db, &podcasts_dir, episodes_count, with_sidecars, http)?;
+
+    storage.save(&podder_db)?;
 
-    let db_content = fs::read_to_string(&db_file_path)
-        .context("Failed to read podder_db.json")?;
+    Ok(())
+}
+
+fn import_opml(opml_path: &str, path: &str) -> Result<()> {
+    println!("Importing podcasts from OPML file: {}", opml_path);
+
+    let opml_content = fs::read_to_string(opml_path)
+        .with_context(|| format!("Failed to read OPML file: {}", opml_path))?;
+
+    let opml = OPML::from_str(&opml_content)
+        .context("Failed to parse OPML file")?;
+
+    let base_path = Path::new(path);
+    let storage = open_storage(base_path)?;
+
+    let mut podder_db = if storage.exists() {
+        storage.load()?
+    } else {
+        fs::create_dir_all(base_path)
+            .with_context(|| format!("Failed to create output directory: {}", path))?;
+        PodderDB::default()
+    };
 
-    let mut podder_db: PodderDB = serde_json::from_str(&db_content)
-        .context("Failed to parse podder_db.json")?;
+    let import = podder_db.import_opml(&opml);
 
     let podcasts_dir = base_path.join("podcasts");
+    for podcast in &podder_db.podcasts {
+        fs::create_dir_all(podcasts_dir.join(podcast.filename()))
+            .with_context(|| format!("Failed to create directory for podcast: {}", podcast.title))?;
+    }
+
+    storage.save(&podder_db)?;
+
+    println!(
+        "Imported {} new podcasts, {} already subscribed, {} invalid outlines",
+        import.added,
+        import.merged,
+        import.invalid,
+    );
+    if import.added > 0 {
+        println!("Run `oxipodder update` to fetch their episodes");
+    }
+
+    Ok(())
+}
+
+fn export_opml(path: &str, opml_path: &str, with_tags: bool) -> Result<()> {
+    let (_, podder_db) = load_db(Path::new(path))?;
+
+    let opml = podder_db.export_opml(with_tags)?;
+
+    if opml_path == "-" {
+        print!("{opml}");
+    } else {
+        fs::write(opml_path, opml)
+            .with_context(|| format!("Failed to write OPML file: {}", opml_path))?;
+        println!("Exported {} podcasts to {}", podder_db.podcasts.len(), opml_path);
+    }
+
+    Ok(())
+}
+
+fn add_podcast(path: &str, url: Url, auth: Option<FeedAuth>, refresh_options: &RefreshOptions) -> Result<()> {
+    let base_path = Path::new(path);
+    let storage = open_storage(base_path)?;
+
+    let mut podder_db = if storage.exists() {
+        storage.load()?
+    } else {
+        fs::create_dir_all(base_path)
+            .with_context(|| format!("Failed to create output directory: {}", path))?;
+        PodderDB::default()
+    };
+
+    let podcast = podder_db.subscribe(url, auth, refresh_options)?;
+    fs::create_dir_all(base_path.join(PODCAST_DIR).join(podcast.filename()))
+        .with_context(|| format!("Failed to create directory for podcast: {}", podcast.title))?;
+    println!("Added {} from {} ({} episodes)", podcast.title, podcast.public_xml_url(), podcast.episodes.len());
+
+    storage.save(&podder_db)?;
+
+    Ok(())
+}
+
+fn remove_podcast(path: &str, podcast: &str, delete_files: bool) -> Result<()> {
+    let base_path = Path::new(path);
+    let (storage, mut podder_db) = load_db(base_path)?;
+
+    let removed = podder_db.unsubscribe(podcast)
+        .with_context(|| format!("No podcast matching {podcast:?}"))?;
+
+    storage.save(&podder_db)?;
+
+    let podcasts_dir = base_path.join(PODCAST_DIR);
+    let podcast_dir = podcasts_dir.join(removed.filename());
+    if delete_files && podcast_dir.exists() {
+        // Never delete anything but a folder directly inside the podcasts directory.
+        let parent = fs::canonicalize(&podcast_dir)?.parent().map(Path::to_path_buf);
+        if parent != Some(fs::canonicalize(&podcasts_dir)?) {
+            return Err(anyhow!("Refusing to delete {:?}: it is not a podcast folder inside {:?}", podcast_dir, podcasts_dir));
+        }
+        fs::remove_dir_all(&podcast_dir)
+            .with_context(|| format!("Failed to delete {:?}", podcast_dir))?;
+        println!("Removed {} and deleted {:?}", removed.title, podcast_dir);
+    } else if podcast_dir.exists() {
+        println!("Removed {}, downloaded episodes kept in {:?}", removed.title, podcast_dir);
+    } else {
+        println!("Removed {}", removed.title);
+    }
+
+    Ok(())
+}
+
+fn migrate_storage(path: &str, target: StorageKind) -> Result<()> {
+    let base_path = Path::new(path);
+    let (source, podder_db) = load_db(base_path)?;
+    if source.kind() == target {
+        return Err(anyhow::anyhow!("The database at {:?} already uses {} storage", source.location(), target));
+    }
+
+    let destination = storage(base_path, target)?;
+    if destination.exists() {
+        return Err(anyhow::anyhow!("{:?} already exists, move it away first", destination.location()));
+    }
+    destination.save(&podder_db)?;
+
+    // Keep the old file around, but out of the way of storage detection.
+    let mut retired = source.location().as_os_str().to_owned();
+    retired.push(".migrated");
+    fs::rename(source.location(), &retired)
+        .with_context(|| format!("Failed to move {:?} aside", source.location()))?;
+
+    println!(
+        "Migrated {} podcasts from {} to {} storage, old database kept at {:?}",
+        podder_db.podcasts.len(),
+        source.kind(),
+        target,
+        retired,
+    );
+
+    Ok(())
+}
+
+fn check_schema(path: &str) -> Result<()> {
+    let storage = open_storage(Path::new(path))?;
+    if !storage.exists() {
+        return Err(anyhow::anyhow!("No podcast database found in {:?}", path));
+    }
+
+    let steps = storage.pending_migrations()?;
+    if steps.is_empty() {
+        println!("{:?} is up to date (schema v{})", storage.location(), SCHEMA_VERSION);
+    }
+    for step in &steps {
+        println!("v{} -> v{}: {} ({} would change)", step.from, step.to, step.description, step.changes);
+    }
+
+    Ok(())
+}
+
+fn upgrade_schema(path: &str) -> Result<()> {
+    let storage = open_storage(Path::new(path))?;
+    let steps = storage.pending_migrations()?;
+    if steps.is_empty() {
+        println!("{:?} is up to date (schema v{})", storage.location(), SCHEMA_VERSION);
+        return Ok(());
+    }
+
+    let podder_db = storage.load()?;
+    storage.save(&podder_db)?;
+
+    Ok(())
+}
+
+fn list_backups(path: &str) -> Result<()> {
+    let storage = open_storage(Path::new(path))?;
+    let backups = storage.backups().list()?;
+    if backups.is_empty() {
+        println!("No backups in {:?}", storage.backups().dir());
+    }
+    for backup in backups {
+        println!("{}", backup.file_name().unwrap_or_default().to_string_lossy());
+    }
+
+    Ok(())
+}
+
+fn restore_backup(path: &str, backup: Option<&str>) -> Result<()> {
+    let storage = open_storage(Path::new(path))?;
+    let backup = match backup {
+        // A bare name refers to a file in the backup directory, as printed by --list.
+        Some(name) if !Path::new(name).exists() => storage.backups().dir().join(name),
+        Some(file) => PathBuf::from(file),
+        None => storage.backups().list()?.into_iter().next()
+            .with_context(|| format!("No backups in {:?}", storage.backups().dir()))?,
+    };
+
+    storage.restore(&backup)?;
+    println!("Restored {:?} from {:?}", storage.location(), backup);
+
+    Ok(())
+}
 
-    download_episodes_from_db(&mut podder_db, &podcasts_dir, episodes_count)?;
+fn set_podcast_auth(path: &str, podcast: &str, auth: Option<FeedAuth>, url_query_keys: &[String]) -> Result<()> {
+    let (storage, mut podder_db) = load_db(Path::new(path))?;
 
-    let updated_db_content = serde_json::to_string_pretty(&podder_db)
-        .context("Failed to serialize updated database")?;
+    let pod = podder_db.find_podcast_mut(podcast)
+        .with_context(|| format!("No podcast matching {podcast:?}"))?;
+    let mut auth = auth.unwrap_or_default();
+    auth.take_query_from(&mut pod.xml_url, url_query_keys)?;
+    pod.auth = Some(auth).filter(|a| !a.is_empty());
+    match pod.auth {
+        Some(_) => println!("Updated credentials for {}", pod.title),
+        None => println!("Cleared credentials for {}", pod.title),
+    }
 
-    fs::write(&db_file_path, updated_db_content)
-        .context("Failed to save updated database")?;
+    storage.save(&podder_db)?;
 
     Ok(())
 }
 
+fn print_download_summary(results: &DownloadResults, display_names: &[String]) {
+    if !results.failed.is_empty() {
+        println!("Failed downloads:");
+    }
+    for failure in &results.failed {
+        let name = display_names.get(failure.id as usize).map(String::as_str).unwrap_or_default();
+        println!("! {name}: {}: {}", failure.kind, failure.message);
+    }
+
+    let mut kinds: Vec<(String, usize)> = Vec::new();
+    for failure in &results.failed {
+        let kind = match failure.kind {
+            DownloadErrorKind::HttpStatus(_) => "HTTP status".to_string(),
+            kind => kind.to_string(),
+        };
+        match kinds.iter_mut().find(|(k, _)| *k == kind) {
+            Some((_, count)) => *count += 1,
+            None => kinds.push((kind, 1)),
+        }
+    }
+    let breakdown = kinds.iter().map(|(kind, count)| format!("{count} {kind}")).collect::<Vec<_>>().join(", ");
+
+    if results.failed.is_empty() {
+        println!("Downloaded {} files", results.completed.len());
+    } else {
+        println!("Downloaded {} files, {} failed ({breakdown})", results.completed.len(), results.failed.len());
+    }
+}
+
 fn download_episodes_from_db(
     podder_db: &mut PodderDB,
     podcasts_dir: &Path,
     episodes_count: usize,
+    with_sidecars: bool,
+    http: &HttpConfig,
 ) -> Result<()> {
-    // TODO: do async downloading
-    let client = Client::new();
     let mut display_name: Vec<String> = Vec::new();
     let mut download_list: Vec<DownloadQueueElement> = Vec::new();
+    // Queue id -> (podcast, episode) index, so episodes are only marked once their audio has arrived.
+    let mut queued_episodes: HashMap<u32, (usize, usize)> = HashMap::new();
     let mut count: u32 = 0;
-    for podcast in &mut podder_db.podcasts {
+    for (podcast_index, podcast) in podder_db.podcasts.iter_mut().enumerate() {
         let dir_name = podcast.filename();
         let podcast_dir = podcasts_dir.join(&dir_name);
 
         fs::create_dir_all(&podcast_dir)
             .with_context(|| format!("Failed to create directory for podcast: {}", podcast.title))?;
 
-        podcast.episodes.sort_by(|a, b| b.pub_date.cmp(&a.pub_date));
+        podcast.episodes.sort_by_key(|e| std::cmp::Reverse(e.pub_date));
 
-        let mut episodes_to_download = podcast.episodes
-            .iter_mut()
-            .take(episodes_count)
-            .filter(|e| !e.downloaded_on_last_sync && !e.listened_to);
+        let episodes_to_download = podcast.episodes
+            .iter()
+            .enumerate()
+            .filter(|(i, e)| !e.withdrawn && (e.redownload || (*i < episodes_count && !e.downloaded_on_last_sync && !e.listened_to)));
 
-        for episode in &mut episodes_to_download {
+        for (episode_index, episode) in episodes_to_download {
             let episode_path = podcast_dir.join(episode.filename());
 
-            if episode_path.exists() {
+            if episode_path.exists() && !episode.redownload {
                 continue;
             }
-            episode.downloaded_on_last_sync = true;
+            let Ok(url) = Url::from_str(&episode.enclosure.url) else {
+                println!("Skipping {} - {}: invalid enclosure URL {:?}", podcast.title, episode.title, episode.enclosure.url);
+                continue;
+            };
+            queued_episodes.insert(count, (podcast_index, episode_index));
 
             display_name.push(format!("{} - {}", podcast.title, episode.title));
             download_list.push(DownloadQueueElement {
                 name: episode.title.clone(),
                 id: count,
-                url: Url::from_str(&episode.enclosure.url).unwrap(),
+                auth: podcast.auth_for(&url),
+                url,
                 location: episode_path,
-                pub_date: episode.pub_date
+                pub_date: episode.pub_date,
+                kind: DownloadKind::Audio,
+                expected_size: u64::try_from(episode.enclosure.length).ok().filter(|l| *l > 0),
             });
             count += 1;
         }
 
+        if with_sidecars {
+            let queued: Vec<usize> = queued_episodes.values()
+                .filter(|(p, _)| *p == podcast_index)
+                .map(|(_, e)| *e)
+                .collect();
+            for (_, episode) in podcast.episodes.iter().enumerate().filter(|(i, e)| e.downloaded_on_last_sync || queued.contains(i)) {
+                for (filename, url) in episode.sidecar_files() {
+                    let location = podcast_dir.join(&filename);
+                    let Ok(url) = Url::parse(&url) else { continue };
+                    if location.exists() {
+                        continue;
+                    }
+
+                    display_name.push(format!("{} - {}", podcast.title, filename));
+                    download_list.push(DownloadQueueElement {
+                        name: filename,
+                        id: count,
+                        auth: podcast.auth_for(&url),
+                        url,
+                        location,
+                        pub_date: episode.pub_date,
+                        kind: DownloadKind::Sidecar,
+                        expected_size: None,
+                    });
+                    count += 1;
+                }
+            }
+        }
 
     }
-    if download_list.len() == 0 {
+    if download_list.is_empty() {
         println!("None to download");
         return Ok(());
     }
-    let (rx, handles) = create_downloader(download_list, 16).unwrap();
+    let (rx, handles) = create_downloader(download_list, 16, http)?;
 
-    create_download_view(rx, handles, display_name).unwrap();
+    let results = create_download_view(rx, handles, display_name.clone())?;
+    for (podcast_index, episode_index) in results.completed.iter().filter_map(|id| queued_episodes.get(id)) {
+        let episode = &mut podder_db.podcasts[*podcast_index].episodes[*episode_index];
+        episode.downloaded_on_last_sync = true;
+        episode.redownload = false;
+        episode.download_failure = None;
+    }
+    for failure in &results.failed {
+        if let Some((podcast_index, episode_index)) = queued_episodes.get(&failure.id) {
+            podder_db.podcasts[*podcast_index].episodes[*episode_index]
+                .record_download_failure(failure.kind, failure.message.clone());
+        }
+    }
 
-    println!("Downloaded Episodes");
+    print_download_summary(&results, &display_name);
 
     Ok(())
 }